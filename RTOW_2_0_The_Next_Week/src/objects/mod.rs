@@ -7,6 +7,7 @@ pub mod rectangles;
 pub mod rtow_box;
 pub mod transformed;
 pub mod volumes;
+pub mod sky;
 
 
 pub mod prelude;
\ No newline at end of file
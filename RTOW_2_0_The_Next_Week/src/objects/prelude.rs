@@ -6,4 +6,5 @@ pub use crate::objects::hittable_list::*;
 pub use crate::objects::rectangles::*;
 pub use crate::objects::rtow_box::*;
 pub use crate::objects::transformed::*;
-pub use crate::objects::volumes::*;
\ No newline at end of file
+pub use crate::objects::volumes::*;
+pub use crate::objects::sky::*;
\ No newline at end of file
@@ -9,6 +9,7 @@ use crate::objects::{
     hit::*,
     sphere::*,
     lights::*,
+    sky::*,
 };
 
 use crate::rtow_math::{
@@ -40,7 +41,7 @@ fn light_hits(r: &ray, lights: Arc<Vec<light>>, obj: Arc<Vec<Box<dyn Hittable>>>
     color
 }
 
-fn ray_hits(r: &ray, obj: Arc<Vec<Box<dyn Hittable>>>, depth_: i32) ->  colorRGB {
+fn ray_hits(r: &ray, obj: Arc<Vec<Box<dyn Hittable>>>, depth_: i32, sky: &physical_sky) ->  colorRGB {
     if(depth_ < 1) {return colorRGB::new()}
 
     let next_depth = depth_ -1;
@@ -57,15 +58,13 @@ fn ray_hits(r: &ray, obj: Arc<Vec<Box<dyn Hittable>>>, depth_: i32) ->  colorRGB
         //let mut attenuation = colorRGB::new();
         unsafe{
         if(Material::scatter(&*rec.mat, r, &rec, &mut attenuation, &mut scattered)){
-            return ray_hits(&scattered, obj, next_depth) * attenuation;
+            return ray_hits(&scattered, obj, next_depth, sky) * attenuation;
         }
     }
         return colorRGB::from(0.5,0.5,0.5);
     }
 
-    let unit_dir = r.dir.unit_vec();
-    let t = 0.5 * (unit_dir.y() + 1.0);
-    colorRGB::from(1.,1.,1.)*(1.0 - t) + colorRGB::from(0.5, 0.7, 1.0) * t
+    sky.value(&r.dir)
 }
 
 static samples: i32 = 100;
@@ -102,6 +101,9 @@ pub fn shadow_rays() {
     
     let arc_lights = Arc::new(lights);
 
+    // What the rays that miss see, an afternoon sun
+    let arc_sky = Arc::new(physical_sky::from_angles(35., 60., 3., colorRGB::from(0.3, 0.3, 0.3)));
+
     // SETUP Objects and materials 
     let mut hittables: Vec<Box<dyn Hittable>> = Vec::new();
     let mut material_vec : Vec<Arc<dyn Material>> = Vec::new();
@@ -186,6 +188,7 @@ pub fn shadow_rays() {
                 let idx = (image_width * (image_height - i - 1 ) as usize + j ); //as usize;
                 
                 let light_arc = Arc::clone(&arc_lights);
+                let sky_arc = Arc::clone(&arc_sky);
 
                 let sender_cpy = sender.clone();
                 tp.add_task(move || {
@@ -195,7 +198,7 @@ pub fn shadow_rays() {
                         let u = (float_j + rand_f64()) / (iw_f64 - 1.);
                         let v = (float_i + rand_f64()) / (ih_f64 - 1.);
                         let r = cam.focus_ray(u, v);
-                        let ambient_indirect = ray_hits(&r, Arc::clone(&hit_arc), depth);
+                        let ambient_indirect = ray_hits(&r, Arc::clone(&hit_arc), depth, &sky_arc);
                         let lights_direct = light_hits(&r, Arc::clone(&light_arc), Arc::clone(&hit_arc));
                         pixel = pixel + ambient_indirect + lights_direct;
                     }                   
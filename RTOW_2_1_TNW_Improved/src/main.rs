@@ -55,6 +55,13 @@ fn rtow_tnw_fns() {
         //rayon_chunks::render();
         event!(Level::TRACE, "Entering Render");
         rayon_tiles::render();
+        //light_sampling::render();
+        //light_sampling::render_delta_lights();
+        //light_sampling::render_emissive_spheres();
+        //light_sampling::render_environment();
+        //light_sampling::render_daylight();
+        //spectral::render();
+        //bdpt::render();
         eprintln!("After Iterative Physical Mem: {}", usage.physical_mem / 1024 / 1024);
         eprintln!("After Iterative Virtual Mem: {}", usage.virtual_mem / 1024 / 1024);
         
@@ -5,15 +5,51 @@ use crate::rtow_math::{
     rng::*,
     camera::*,
     defines::*,
+    spectrum::*,
 };
 
 pub trait Emissive {
     
 }
 
+/// Area light, emits tex * albedo evenly in all directions (lambertian emitter)
+/// tex can be anything: an image for screens, a mask for neon signs...
+/// One sided lights only emit from the front face, the side the object's normal points to
 pub struct Diffuse_Emissive {
     pub albedo: colorRGB,
     pub tex: Arc<dyn Texture>,
+    pub two_sided: bool,
+}
+
+// Luminous efficacy of 555nm light, the most lumens a watt can give
+const LUMENS_PER_WATT: f64 = 683.;
+
+impl Diffuse_Emissive {
+    pub fn new(tex: Arc<dyn Texture>) -> Diffuse_Emissive {
+        Diffuse_Emissive { albedo: colorRGB::one(), tex, two_sided: true }
+    }
+
+    /// Colour of a black body at kelvin, intensity is its luminance
+    pub fn from_kelvin(kelvin: f64, intensity: f64) -> Diffuse_Emissive {
+        Diffuse_Emissive { albedo: blackbody(kelvin) * intensity, ..Diffuse_Emissive::new(Arc::new(Solid_Color::from(1., 1., 1.))) }
+    }
+
+    /// The shape built by shape(material) glowing with watts spread over its area (Hittable::area()),
+    /// the same power whatever its size. tex gives the colour, it is not normalized so keep it around 1
+    /// e.g. Diffuse_Emissive::shape_with_power(tex, 100., false, |mat| quad::from(q, u, v, mat))
+    pub fn shape_with_power<H: Hittable>(tex: Arc<dyn Texture>, watts: f64, two_sided: bool, shape: impl Fn(Arc<dyn Material>) -> H) -> H {
+        // Only the geometry matters for the area, the emission is set once it is known
+        let area = shape(Arc::new(Diffuse_Emissive::new(Arc::clone(&tex)))).area();
+        if area <= 0. { panic!("shape_with_power needs a shape that knows its area") }
+        // A lambertian emitter sends pi * radiance per unit area out of each face
+        let faces = if two_sided { 2. } else { 1. };
+        shape(Arc::new(Diffuse_Emissive { albedo: colorRGB::one() * (watts / (pi * area * faces)), tex, two_sided }))
+    }
+
+    /// Same as shape_with_power in lumens, tex should have a luminance of 1 like blackbody() colours
+    pub fn shape_with_lumens<H: Hittable>(tex: Arc<dyn Texture>, lumens: f64, two_sided: bool, shape: impl Fn(Arc<dyn Material>) -> H) -> H {
+        Diffuse_Emissive::shape_with_power(tex, lumens / LUMENS_PER_WATT, two_sided, shape)
+    }
 }
 
 impl Material for Diffuse_Emissive {
@@ -26,9 +62,53 @@ impl Material for Diffuse_Emissive {
     }
 
     fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
-        if v.is_nan() {
-            let t = true;
-        }
         self.tex.value(u,v,p) * self.albedo
     }
+
+    fn emitted_hit(&self, r: &ray, rec: &hit_record) -> colorRGB {
+        if !self.two_sided && !rec.front_face { return colorRGB::new() }
+        rec.texture(&self.tex) * self.albedo
+    }
+
+    // The texture averaged over a grid of uvs, enough to rank lights
+    fn light_emission(&self) -> Option<(f64, bool)> {
+        let n = 4;
+        let mut sum = colorRGB::new();
+        for i in 0..n {
+            for j in 0..n {
+                let (u, v) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                sum = sum + self.tex.value(u, v, &point3::new()) * self.albedo;
+            }
+        }
+        let c = sum / (n * n) as f64;
+        Some((0.2126 * c.v[0] + 0.7152 * c.v[1] + 0.0722 * c.v[2], self.two_sided))
+    }
+}
+
+/// Material of an emissive medium, scatters like its phase function and glows with tex
+pub struct Medium_Emissive {
+    pub phase: Arc<dyn Material>,
+    pub tex: Arc<dyn Texture>,
+}
+
+impl Material for Medium_Emissive {
+    fn scatter(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.phase.scatter(r, rec, attenuation, scatter)
+    }
+
+    fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.phase.scatter_tex(r, rec, attenuation, scatter)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.tex.value(u, v, p)
+    }
+
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        self.phase.eval(r, rec, scattered)
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        self.phase.scattering_pdf(r, rec, scattered)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,141 @@
+use crate::materials::*;
+use crate::materials::microfacet::*;
+use crate::rtow_math::prelude::*;
+
+// Materials made of other materials, the choice between them is taken once per hit.
+// scatter_tex, eval and scattering_pdf are called separately for the same hit (light sampling),
+// so the choice comes from a hash of the ray and the hit instead of rand_f64(): all of them see
+// the same pick and the material behaves exactly as the picked one, specular parts included.
+
+/// Uniform number in [0, 1) that is always the same for a given ray and hit
+fn hit_random(r: &ray, rec: &hit_record) -> f64 {
+    let mut h: u64 = 0x9E3779B97F4A7C15;
+    for x in rec.p.v.iter().chain(r.dir.v.iter()) {
+        // splitmix64 step over the bits of each coordinate
+        h = (h ^ x.to_bits()).wrapping_add(0x9E3779B97F4A7C15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94D049BB133111EB);
+        h ^= h >> 31;
+    }
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// a where the first channel of mask is 0, b where it is 1, randomly in between
+pub struct mix_material {
+    pub a: Arc<dyn Material>,
+    pub b: Arc<dyn Material>,
+    pub mask: Arc<dyn Texture>,
+}
+
+impl mix_material {
+    pub fn from(a: Arc<dyn Material>, b: Arc<dyn Material>, mask: Arc<dyn Texture>) -> mix_material {
+        mix_material { a, b, mask }
+    }
+
+    fn amount(&self, u: f64, v: f64, p: &point3) -> f64 {
+        self.mask.value(u, v, p).v[0].clamp(0., 1.)
+    }
+
+    fn pick(&self, r: &ray, rec: &hit_record) -> &Arc<dyn Material> {
+        if hit_random(r, rec) < rec.texture(&self.mask).v[0].clamp(0., 1.) { &self.b } else { &self.a }
+    }
+}
+
+impl Material for mix_material {
+    fn scatter(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.pick(r, rec).scatter(r, rec, attenuation, scatter)
+    }
+
+    fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.pick(r, rec).scatter_tex(r, rec, attenuation, scatter)
+    }
+
+    // Emission is blended instead of picked, a masked light (neon signs) is less noisy that way
+    fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        let m = self.amount(u, v, p);
+        self.a.emitted(u, v, p) * (1. - m) + self.b.emitted(u, v, p) * m
+    }
+
+    fn emitted_hit(&self, r: &ray, rec: &hit_record) -> colorRGB {
+        let m = rec.texture(&self.mask).v[0].clamp(0., 1.);
+        self.a.emitted_hit(r, rec) * (1. - m) + self.b.emitted_hit(r, rec) * m
+    }
+
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        self.pick(r, rec).eval(r, rec, scattered)
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        self.pick(r, rec).scattering_pdf(r, rec, scattered)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.a.is_dispersive() || self.b.is_dispersive()
+    }
+}
+
+/// Smooth clear coat (varnish, car paint, lacquered wood) over any base material
+/// Light either reflects on the coat by its Fresnel or goes to the base, and what comes out of
+/// the base loses what the coat reflects back inside. Refraction through the thin coat is ignored.
+pub struct coated {
+    pub base: Arc<dyn Material>,
+    pub index_refr: f64,
+}
+
+impl coated {
+    pub fn from(base: Arc<dyn Material>, index_refr: f64) -> coated {
+        coated { base, index_refr }
+    }
+
+    fn fresnel(&self, dir: &vec3, rec: &hit_record) -> f64 {
+        fresnel_dielectric(dir.unit_vec().dot(&rec.n).abs(), self.index_refr)
+    }
+
+    fn coat_reflects(&self, r: &ray, rec: &hit_record) -> bool {
+        rec.front_face && hit_random(r, rec) < self.fresnel(&r.dir, rec)
+    }
+}
+
+impl Material for coated {
+    fn scatter(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.scatter_tex(r, rec, attenuation, scatter)
+    }
+
+    fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        if self.coat_reflects(r, rec) {
+            *scatter = ray::from_t(rec.p, r.dir.unit_vec().reflect(&rec.n), r.time);
+            *attenuation = colorRGB::one();
+            return true
+        }
+
+        if !self.base.scatter_tex(r, rec, attenuation, scatter) { return false }
+        if rec.front_face && scatter.dir.dot(&rec.n) > 0. {
+            *attenuation = *attenuation * (1. - self.fresnel(&scatter.dir, rec));
+        }
+        true
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.base.emitted(u, v, p)
+    }
+
+    fn emitted_hit(&self, r: &ray, rec: &hit_record) -> colorRGB {
+        self.base.emitted_hit(r, rec)
+    }
+
+    // The coat reflection is specular, only the base takes light samples
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        if self.coat_reflects(r, rec) { return colorRGB::new() }
+        let f = self.base.eval(r, rec, scattered);
+        if rec.front_face && scattered.dir.dot(&rec.n) > 0. { f * (1. - self.fresnel(&scattered.dir, rec)) } else { f }
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        if self.coat_reflects(r, rec) { return 0. }
+        self.base.scattering_pdf(r, rec, scattered)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+}
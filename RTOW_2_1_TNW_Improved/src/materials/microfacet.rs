@@ -0,0 +1,332 @@
+use crate::materials::*;
+use crate::rtow_math::prelude::*;
+
+// GGX / Trowbridge-Reitz microfacet helpers, all directions are in the local frame
+// of the shading normal (z up) and point away from the surface
+// alpha is the width of the distribution, alpha = roughness^2 reads more linear to the eye
+
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    (roughness * roughness).max(0.001)
+}
+
+/// Distribution of normals, density of microfacets facing h
+/// alpha_x and alpha_y stretch it along the u and v of the frame for anisotropic surfaces
+pub fn ggx_d_aniso(h: &vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    if h.v[2] <= 0. { return 0. }
+    let (x, y) = (h.v[0] / alpha_x, h.v[1] / alpha_y);
+    let denom = x * x + y * y + h.v[2] * h.v[2];
+    1. / (pi * alpha_x * alpha_y * denom * denom)
+}
+
+pub fn ggx_d(h: &vec3, alpha: f64) -> f64 {
+    ggx_d_aniso(h, alpha, alpha)
+}
+
+fn ggx_lambda(w: &vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    let cos2 = w.v[2] * w.v[2];
+    if cos2 <= 0. { return INFINITY }
+    let (x, y) = (alpha_x * w.v[0], alpha_y * w.v[1]);
+    let alpha2_tan2 = (x * x + y * y) / cos2;
+    ((1. + alpha2_tan2).sqrt() - 1.) / 2.
+}
+
+/// Smith masking, how much of the microsurface is visible from w
+pub fn smith_g1_aniso(w: &vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    1. / (1. + ggx_lambda(w, alpha_x, alpha_y))
+}
+
+pub fn smith_g1(w: &vec3, alpha: f64) -> f64 {
+    smith_g1_aniso(w, alpha, alpha)
+}
+
+/// Height correlated masking-shadowing of wo and wi together
+pub fn smith_g2_aniso(wo: &vec3, wi: &vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    1. / (1. + ggx_lambda(wo, alpha_x, alpha_y) + ggx_lambda(wi, alpha_x, alpha_y))
+}
+
+pub fn smith_g2(wo: &vec3, wi: &vec3, alpha: f64) -> f64 {
+    smith_g2_aniso(wo, wi, alpha, alpha)
+}
+
+/// Microfacet normal sampled from the normals visible from wo (Heitz 2018)
+/// pdf(h) = g1(wo) * max(0, wo.h) * d(h) / wo.z
+pub fn sample_ggx_vndf_aniso(wo: &vec3, alpha_x: f64, alpha_y: f64) -> vec3 {
+    // Stretch to the hemisphere configuration
+    let vh = vec3::from(alpha_x * wo.v[0], alpha_y * wo.v[1], wo.v[2]).unit_vec();
+    let lensq = vh.v[0] * vh.v[0] + vh.v[1] * vh.v[1];
+    let t1 = if lensq > 0. { vec3::from(-vh.v[1], vh.v[0], 0.) / lensq.sqrt() } else { vec3::from(1., 0., 0.) };
+    let t2 = vh.cross(&t1);
+
+    // Disk sample, squashed towards the visible half
+    let r = rand_f64().sqrt();
+    let phi = 2. * pi * rand_f64();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1. + vh.v[2]);
+    let p2 = (1. - s) * (1. - p1 * p1).max(0.).sqrt() + s * r * phi.sin();
+
+    let nh = t1 * p1 + t2 * p2 + vh * (1. - p1 * p1 - p2 * p2).max(0.).sqrt();
+    vec3::from(alpha_x * nh.v[0], alpha_y * nh.v[1], nh.v[2].max(0.)).unit_vec()
+}
+
+pub fn sample_ggx_vndf(wo: &vec3, alpha: f64) -> vec3 {
+    sample_ggx_vndf_aniso(wo, alpha, alpha)
+}
+
+/// Pdf of sample_ggx_vndf returning h
+pub fn ggx_vndf_pdf_aniso(wo: &vec3, h: &vec3, alpha_x: f64, alpha_y: f64) -> f64 {
+    if wo.v[2] <= 0. { return 0. }
+    smith_g1_aniso(wo, alpha_x, alpha_y) * wo.dot(h).max(0.) * ggx_d_aniso(h, alpha_x, alpha_y) / wo.v[2]
+}
+
+pub fn ggx_vndf_pdf(wo: &vec3, h: &vec3, alpha: f64) -> f64 {
+    ggx_vndf_pdf_aniso(wo, h, alpha, alpha)
+}
+
+/// Exact Fresnel reflectance of a conductor with complex IOR eta + i*k, per channel
+pub fn fresnel_conductor(cos: f64, eta: &colorRGB, k: &colorRGB) -> colorRGB {
+    let cos = cos.clamp(0., 1.);
+    let cos2 = cos * cos;
+    let sin2 = 1. - cos2;
+    let mut ret = colorRGB::new();
+    for i in 0..3 {
+        let (eta2, k2) = (eta.v[i] * eta.v[i], k.v[i] * k.v[i]);
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4. * eta2 * k2).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2. * cos * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        ret.v[i] = 0.5 * (rs + rp);
+    }
+    ret
+}
+
+/// Rough metal, GGX distribution with Smith masking-shadowing and the Fresnel of its complex IOR
+/// Only single scattering on the microfacets, very rough metal loses a bit of energy
+pub struct conductor {
+    pub eta: colorRGB,
+    pub k: colorRGB,
+    pub roughness: f64,
+}
+
+impl conductor {
+    pub fn from(eta: colorRGB, k: colorRGB, roughness: f64) -> conductor {
+        conductor { eta, k, roughness }
+    }
+
+    // Complex IOR sampled at ~650, 550 and 450nm
+
+    pub fn gold(roughness: f64) -> conductor {
+        conductor::from(colorRGB::from(0.143, 0.374, 1.442), colorRGB::from(3.983, 2.385, 1.603), roughness)
+    }
+
+    pub fn copper(roughness: f64) -> conductor {
+        conductor::from(colorRGB::from(0.200, 0.924, 1.102), colorRGB::from(3.912, 2.452, 2.142), roughness)
+    }
+
+    pub fn aluminium(roughness: f64) -> conductor {
+        conductor::from(colorRGB::from(1.657, 0.880, 0.521), colorRGB::from(9.224, 6.270, 4.837), roughness)
+    }
+
+    pub fn silver(roughness: f64) -> conductor {
+        conductor::from(colorRGB::from(0.155, 0.117, 0.138), colorRGB::from(4.828, 3.122, 2.147), roughness)
+    }
+
+    /// wo and wi in the frame of the normal
+    fn local_dirs(r: &ray, rec: &hit_record, scattered: &ray) -> (vec3, vec3) {
+        let frame = onb::build_from_w(&rec.n);
+        (frame.to_local(&(r.dir.unit_vec() * -1.)), frame.to_local(&scattered.dir.unit_vec()))
+    }
+}
+
+impl Material for conductor {
+    fn scatter(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.scatter_tex(r, rec, attenuation, scatter)
+    }
+
+    fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        let alpha = roughness_to_alpha(self.roughness);
+        let frame = onb::build_from_w(&rec.n);
+        let wo = frame.to_local(&(r.dir.unit_vec() * -1.));
+        if wo.v[2] <= 0. { return false }
+
+        let h = sample_ggx_vndf(&wo, alpha);
+        let wi = h * (2. * wo.dot(&h)) - wo;
+        if wi.v[2] <= 0. { return false }
+
+        // eval / pdf, D and the 4 wo.z cancel out
+        *attenuation = fresnel_conductor(wo.dot(&h), &self.eta, &self.k) * (smith_g2(&wo, &wi, alpha) / smith_g1(&wo, alpha));
+        *scatter = ray::from_t(rec.p, frame.local(&wi), r.time);
+        true
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::new()
+    }
+
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        let alpha = roughness_to_alpha(self.roughness);
+        let (wo, wi) = conductor::local_dirs(r, rec, scattered);
+        if wo.v[2] <= 0. || wi.v[2] <= 0. { return colorRGB::new() }
+
+        let h = (wo + wi).unit_vec();
+        fresnel_conductor(wo.dot(&h), &self.eta, &self.k) * (ggx_d(&h, alpha) * smith_g2(&wo, &wi, alpha) / (4. * wo.v[2]))
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        let alpha = roughness_to_alpha(self.roughness);
+        let (wo, wi) = conductor::local_dirs(r, rec, scattered);
+        if wo.v[2] <= 0. || wi.v[2] <= 0. { return 0. }
+
+        let h = (wo + wi).unit_vec();
+        ggx_vndf_pdf(&wo, &h, alpha) / (4. * wo.dot(&h))
+    }
+}
+
+/// Exact Fresnel reflectance of a dielectric, eta = ior of the side being entered / ior of the current one
+pub fn fresnel_dielectric(cos: f64, eta: f64) -> f64 {
+    let cos = cos.clamp(0., 1.);
+    let sin2_t = (1. - cos * cos) / (eta * eta);
+    // Total internal reflection
+    if sin2_t >= 1. { return 1. }
+
+    let cos_t = (1. - sin2_t).sqrt();
+    let r_parl = (eta * cos - cos_t) / (eta * cos + cos_t);
+    let r_perp = (cos - eta * cos_t) / (cos + eta * cos_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.
+}
+
+/// Rough glass (Walter et al. 2007), GGX microfacets that both reflect and refract
+/// The first channel of roughness is read at the hit, so frosted parts can be painted with a texture
+/// The 1/eta^2 scaling of radiance is left out like in dielectric, it cancels for paths that go in and out
+pub struct rough_dielectric {
+    pub index_refr: f64,
+    pub roughness: Arc<dyn Texture>,
+}
+
+impl rough_dielectric {
+    pub fn from(index_refr: f64, roughness: f64) -> rough_dielectric {
+        rough_dielectric::from_tex(index_refr, Arc::new(Solid_Color::from(roughness, roughness, roughness)))
+    }
+
+    pub fn from_tex(index_refr: f64, roughness: Arc<dyn Texture>) -> rough_dielectric {
+        rough_dielectric { index_refr, roughness }
+    }
+
+    fn alpha(&self, rec: &hit_record) -> f64 {
+        roughness_to_alpha(rec.texture(&self.roughness).v[0].clamp(0., 1.))
+    }
+
+    /// Relative ior across the surface for the side the ray comes from
+    fn eta(&self, rec: &hit_record) -> f64 {
+        if rec.front_face { self.index_refr } else { 1. / self.index_refr }
+    }
+
+    /// (brdf * cos, pdf) of going from wo to wi
+    fn eval_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> (f64, f64) {
+        let frame = onb::build_from_w(&rec.n);
+        let wo = frame.to_local(&(r.dir.unit_vec() * -1.));
+        let wi = frame.to_local(&scattered.dir.unit_vec());
+        dielectric_interface_eval_pdf(&wo, &wi, self.alpha(rec), self.eta(rec))
+    }
+}
+
+/// Microfacet normal that takes wo to wi across an interface of relative ior eta,
+/// None if it faces away from either of them
+fn dielectric_half_vector(wo: &vec3, wi: &vec3, eta: f64) -> Option<vec3> {
+    let reflect = wi.v[2] > 0.;
+    let mut h = if reflect { *wo + *wi } else { *wo + *wi * eta };
+    if h.near_zero() { return None }
+    h = h.unit_vec();
+    if h.v[2] < 0. { h = h * -1. }
+
+    if wo.dot(&h) <= 0. { return None }
+    if reflect != (wi.dot(&h) > 0.) { return None }
+    Some(h)
+}
+
+/// (brdf * cos, pdf) of a rough dielectric interface, reflection and refraction together
+pub fn dielectric_interface_eval_pdf(wo: &vec3, wi: &vec3, alpha: f64, eta: f64) -> (f64, f64) {
+    if wo.v[2] <= 0. || wi.v[2] == 0. { return (0., 0.) }
+
+    let h = match dielectric_half_vector(wo, wi, eta) {
+        Some(h) => h,
+        None => return (0., 0.),
+    };
+
+    let f = fresnel_dielectric(wo.dot(&h), eta);
+    let d = ggx_d(&h, alpha);
+    let g2 = smith_g2(wo, wi, alpha);
+    let vndf = ggx_vndf_pdf(wo, &h, alpha);
+
+    if wi.v[2] > 0. {
+        (f * d * g2 / (4. * wo.v[2]), f * vndf / (4. * wo.dot(&h)))
+    } else {
+        // Jacobian from the half vector to the refracted direction
+        let denom = wo.dot(&h) / eta + wi.dot(&h);
+        let dh_dwi = wi.dot(&h).abs() / (denom * denom);
+        let t = 1. - f;
+        (t * d * g2 * wi.dot(&h).abs() * wo.dot(&h) / (wo.v[2] * denom * denom), t * vndf * dh_dwi)
+    }
+}
+
+/// Reflected or refracted direction picked by the Fresnel of a visible microfacet
+/// The sample weight eval / pdf is g2(wo, wi) / g1(wo)
+pub fn sample_dielectric_interface(wo: &vec3, alpha: f64, eta: f64) -> Option<vec3> {
+    if wo.v[2] <= 0. { return None }
+
+    let h = sample_ggx_vndf(wo, alpha);
+    let cos = wo.dot(&h);
+    if cos <= 0. { return None }
+
+    if rand_f64() < fresnel_dielectric(cos, eta) {
+        let wi = h * (2. * cos) - *wo;
+        if wi.v[2] <= 0. { return None }
+        Some(wi)
+    } else {
+        let cos_t = (1. - (1. - cos * cos) / (eta * eta)).max(0.).sqrt();
+        let wi = *wo * (-1. / eta) + h * (cos / eta - cos_t);
+        if wi.v[2] >= 0. { return None }
+        Some(wi)
+    }
+}
+
+impl Material for rough_dielectric {
+    fn scatter(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.scatter_tex(r, rec, attenuation, scatter)
+    }
+
+    fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        let alpha = self.alpha(rec);
+        let frame = onb::build_from_w(&rec.n);
+        let wo = frame.to_local(&(r.dir.unit_vec() * -1.));
+
+        // The lobe is picked by its Fresnel weight, which then cancels out of the attenuation
+        let wi = match sample_dielectric_interface(&wo, alpha, self.eta(rec)) {
+            Some(wi) => wi,
+            None => return false,
+        };
+
+        *attenuation = colorRGB::one() * (smith_g2(&wo, &wi, alpha) / smith_g1(&wo, alpha));
+        *scatter = ray::from_t(rec.p, frame.local(&wi), r.time);
+        true
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::new()
+    }
+
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        colorRGB::one() * self.eval_pdf(r, rec, scattered).0
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        self.eval_pdf(r, rec, scattered).1
+    }
+}
@@ -1,5 +1,12 @@
 pub mod textures;
 pub mod emissive;
+pub mod phase;
+pub mod microfacet;
+pub mod principled_bsdf;
+pub mod layered;
+pub mod normal_maps;
+pub mod procedural;
+pub mod texture_nodes;
 pub mod prelude;
 // ----------------
 
@@ -24,6 +31,36 @@ pub trait Material {
     fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool;
 
     fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB;
+
+    /// Light leaving the hit towards r, what integrators call. Override for emission that depends
+    /// on the side or direction, the default is emitted() at the hit
+    fn emitted_hit(&self, r: &ray, rec: &hit_record) -> colorRGB {
+        self.emitted(rec.uv.v[0], rec.uv.v[1], &rec.p)
+    }
+
+    // Light sampling, materials that don't override these are treated as perfectly specular
+    // and never take direct light samples
+
+    /// Brdf * cos(theta) from r towards scattered
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        colorRGB::new()
+    }
+
+    /// Pdf of scatter_tex generating the direction of scattered, 0 for specular
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        0.
+    }
+
+    /// True if scattering depends on r.wavelength, the spectral integrator then keeps only the hero wavelength
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+
+    /// Emitters: rough mean luminance of emitted_hit() and whether both faces emit,
+    /// for light samplers to estimate the power of a light. None if it doesn't emit
+    fn light_emission(&self) -> Option<(f64, bool)> {
+        None
+    }
 }
 
 pub struct Default {}
@@ -69,13 +106,14 @@ impl Material for lambertian {
     }
 
     fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
-        let mut scatter_dir = rec.n + random_in_cylindermap();
+        // Normal + point ON the unit sphere gives a cosine distribution, which scattering_pdf relies on
+        let mut scatter_dir = rec.n + random_unit_vector();
+        if(scatter_dir.near_zero()) {
+            scatter_dir = rec.n;
+        }
         scatter_dir = scatter_dir.unit_vec();
-        //if(scatter_dir.near_zero()) {
-        //    scatter_dir = rec.n;
-        //}
         *scatter = ray::from_t(rec.p, scatter_dir, r.time);
-        *attenuation = self.tex.value(rec.uv.v[0], rec.uv.v[1], &rec.p);
+        *attenuation = rec.texture(&self.tex);
         true
     }
 
@@ -83,6 +121,14 @@ impl Material for lambertian {
         colorRGB::new()
     }
 
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        rec.texture(&self.tex) * self.scattering_pdf(r, rec, scattered)
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        let cos = rec.n.dot(&scattered.dir.unit_vec());
+        if cos < 0. { 0. } else { cos / pi }
+    }
 }
 
 /// Metal Materials
@@ -112,7 +158,7 @@ impl Material for metal {
         scatter_dir = (scatter_dir + random_in_sphere() * self.fuzz).unit_vec(); // Fuzzy reflections   
 
         *scatter = ray::from_t(rec.p, scatter_dir, r.time);
-        *attenuation = self.tex.value(rec.uv.v[0], rec.uv.v[1], &rec.p);
+        *attenuation = rec.texture(&self.tex);
         
         (scatter.dir.dot(&rec.n) > 0.)
     }
@@ -124,11 +170,37 @@ impl Material for metal {
 }
 
 /// Dielectric Materials
+/// alpha blends the surface tint from white to albedo/tex, absorption is per unit of distance
+/// travelled inside (Beer-Lambert) so thick parts get a deeper colour than thin ones
 pub struct dielectric {
     pub albedo: colorRGB,
     pub alpha: f64,
     pub index_refr: f64,
     pub tex: Arc<dyn Texture>,
+    pub absorption: colorRGB,
+    pub dispersion: Dispersion,
+}
+
+/// Index of refraction depending on the wavelength, lambda in micrometers
+/// Only the spectral integrator sees it, rgb rendering uses index_refr
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    Constant,
+    /// n = a + b / lambda^2
+    Cauchy(f64, f64),
+    /// n^2 = 1 + sum(b_i * lambda^2 / (lambda^2 - c_i))
+    Sellmeier([f64; 3], [f64; 3]),
+}
+
+impl Dispersion {
+    pub fn ior(&self, wavelength_nm: f64) -> Option<f64> {
+        let l2 = (wavelength_nm / 1000.) * (wavelength_nm / 1000.);
+        match self {
+            Dispersion::Constant => None,
+            Dispersion::Cauchy(a, b) => Some(a + b / l2),
+            Dispersion::Sellmeier(b, c) => Some((1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()).sqrt()),
+        }
+    }
 }
 
 impl dielectric {
@@ -138,11 +210,60 @@ impl dielectric {
             alpha: 0., 
             index_refr: 0.,
             tex: Arc::new(Solid_Color::new()),
+            absorption: colorRGB::new(),
+            dispersion: Dispersion::Constant,
         }
     }
 
     pub fn from(alpha: f64, index_refr: f64, tex: Arc<dyn Texture>) -> dielectric {
-        dielectric{ albedo: colorRGB::one(), alpha, index_refr, tex }
+        dielectric{ albedo: colorRGB::one(), alpha, index_refr, tex, absorption: colorRGB::new(), dispersion: Dispersion::Constant }
+    }
+
+    /// Clear dispersive glass, index_refr is taken at the sodium d line (587.6nm) for rgb rendering
+    pub fn from_dispersion(dispersion: Dispersion) -> dielectric {
+        let index_refr = dispersion.ior(587.6).unwrap_or(1.5);
+        dielectric{ dispersion, ..dielectric::from(0., index_refr, Arc::new(Solid_Color::from(1.,1.,1.))) }
+    }
+
+    pub fn bk7() -> dielectric {
+        dielectric::from_dispersion(Dispersion::Sellmeier([1.03961212, 0.231792344, 1.01046945], [0.00600069867, 0.0200179144, 103.560653]))
+    }
+
+    pub fn diamond() -> dielectric {
+        dielectric::from_dispersion(Dispersion::Sellmeier([0.3306, 4.3356, 0.], [0.030625, 0.011236, 0.]))
+    }
+
+    /// Index of refraction for the wavelength of r
+    fn ior(&self, r: &ray) -> f64 {
+        if r.wavelength <= 0. { return self.index_refr }
+        self.dispersion.ior(r.wavelength).unwrap_or(self.index_refr)
+    }
+
+    /// Clear surface, coloured by absorption coefficients per channel
+    pub fn from_absorption(index_refr: f64, absorption: colorRGB) -> dielectric {
+        dielectric{ absorption, ..dielectric::from(0., index_refr, Arc::new(Solid_Color::from(1.,1.,1.))) }
+    }
+
+    /// Absorption picked so that light keeps col after going through distance of the material
+    pub fn from_transmittance(index_refr: f64, col: colorRGB, distance: f64) -> dielectric {
+        let mut absorption = colorRGB::new();
+        for i in 0..3 {
+            absorption.v[i] = -col.v[i].clamp(0.0001, 1.).ln() / distance;
+        }
+        dielectric::from_absorption(index_refr, absorption)
+    }
+
+    /// Fraction of light left after travelling inside from the origin of r to the hit,
+    /// only when hitting from the inside as r must have started in the material
+    fn transmittance(&self, r: &ray, rec: &hit_record) -> colorRGB {
+        let mut ret = colorRGB::one();
+        if rec.front_face { return ret }
+
+        let dist = rec.t * r.dir.length();
+        for i in 0..3 {
+            ret.v[i] = (-self.absorption.v[i] * dist).exp();
+        }
+        ret
     }
 
     pub fn reflectance(cos: f64, ir: f64) -> f64 {
@@ -154,10 +275,9 @@ impl dielectric {
 
 impl Material for dielectric {
     fn scatter(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
-        *attenuation = self.albedo * self.alpha;
-        *attenuation = (colorRGB::from(1.,1.,1.) + self.albedo*self.alpha);
-        attenuation.clamp(0.,1.);
-        let ratio = if rec.front_face { 1. / self.index_refr} else { self.index_refr };
+        *attenuation = (colorRGB::one() * (1. - self.alpha) + self.albedo * self.alpha) * self.transmittance(r, rec);
+        let ior = self.ior(r);
+        let ratio = if rec.front_face { 1. / ior } else { ior };
         let unit = r.dir.unit_vec();
         
         let cos = (unit * -1.).dot(&rec.n).min(1.);
@@ -174,10 +294,10 @@ impl Material for dielectric {
     }
 
     fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
-        *attenuation = self.tex.value(rec.uv.v[0], rec.uv.v[1], &rec.p) * self.alpha;
-        *attenuation = (colorRGB::from(1.,1.,1.) + self.tex.value(rec.uv.v[0], rec.uv.v[1], &rec.p)*self.alpha);
-        attenuation.clamp(0.,1.);
-        let ratio = if rec.front_face { 1. / self.index_refr} else { self.index_refr };
+        let tint = colorRGB::one() * (1. - self.alpha) + rec.texture(&self.tex) * self.alpha;
+        *attenuation = tint * self.transmittance(r, rec);
+        let ior = self.ior(r);
+        let ratio = if rec.front_face { 1. / ior } else { ior };
         let unit = r.dir.unit_vec();
         
         let cos = (unit * -1.).dot(&rec.n).min(1.);
@@ -196,6 +316,10 @@ impl Material for dielectric {
     fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
         colorRGB::new()
     }
+
+    fn is_dispersive(&self) -> bool {
+        !matches!(self.dispersion, Dispersion::Constant)
+    }
 }
 
 
@@ -218,11 +342,20 @@ impl Material for isotropic {
 
     fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
         *scatter = ray::from_t(rec.p, random_in_sphere(), r.time);
-        *attenuation = self.tex.value(rec.uv.v[0], rec.uv.v[1], &rec.p);
+        *attenuation = rec.texture(&self.tex);
         true
     }
 
     fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
         colorRGB::new()
     }
+
+    // Any direction in the sphere is as likely
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        rec.texture(&self.tex) / (4. * pi)
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        1. / (4. * pi)
+    }
 }
\ No newline at end of file
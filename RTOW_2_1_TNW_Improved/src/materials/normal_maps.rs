@@ -0,0 +1,137 @@
+use crate::materials::*;
+use crate::rtow_math::prelude::*;
+
+// Surface detail without geometry: these wrap a material and hand it a hit_record with the
+// shading normal changed. Every call (scatter, eval, pdf) sees the same normal so light sampling
+// stays consistent. The geometric normal is kept whenever the new one would face away from it.
+
+fn with_normal(rec: &hit_record, n: vec3) -> hit_record {
+    let mut shading = rec.clone();
+    if n.dot(&rec.n) > 0. { shading.n = n.unit_vec() }
+    shading
+}
+
+/// Tangent space normal map, rgb in [0,1] holds the normal in (tangent, bitangent, normal)
+/// As usual for normal maps the image has to be linear, not colour corrected
+pub struct normal_mapped {
+    pub base: Arc<dyn Material>,
+    pub map: Arc<dyn Texture>,
+    // Scales the tangent part of the normal, 0 is flat, 1 is the map as it is
+    pub strength: f64,
+}
+
+impl normal_mapped {
+    pub fn from(base: Arc<dyn Material>, map: Arc<dyn Texture>) -> normal_mapped {
+        normal_mapped { base, map, strength: 1. }
+    }
+
+    pub fn from_strength(base: Arc<dyn Material>, map: Arc<dyn Texture>, strength: f64) -> normal_mapped {
+        normal_mapped { base, map, strength }
+    }
+
+    fn shade(&self, rec: &hit_record) -> hit_record {
+        let c = rec.texture(&self.map);
+        let local = vec3::from(
+            (2. * c.v[0] - 1.) * self.strength,
+            (2. * c.v[1] - 1.) * self.strength,
+            2. * c.v[2] - 1.);
+        with_normal(rec, rec.shading_frame().local(&local))
+    }
+}
+
+impl Material for normal_mapped {
+    fn scatter(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.base.scatter(r, &self.shade(rec), attenuation, scatter)
+    }
+
+    fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.base.scatter_tex(r, &self.shade(rec), attenuation, scatter)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.base.emitted(u, v, p)
+    }
+
+    fn emitted_hit(&self, r: &ray, rec: &hit_record) -> colorRGB {
+        self.base.emitted_hit(r, rec)
+    }
+
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        self.base.eval(r, &self.shade(rec), scattered)
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        self.base.scattering_pdf(r, &self.shade(rec), scattered)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+}
+
+/// Bump map from the first channel of a height texture, scale is the height in world units of a value of 1
+pub struct bump_mapped {
+    pub base: Arc<dyn Material>,
+    pub height: Arc<dyn Texture>,
+    pub scale: f64,
+}
+
+impl bump_mapped {
+    pub fn from(base: Arc<dyn Material>, height: Arc<dyn Texture>, scale: f64) -> bump_mapped {
+        bump_mapped { base, height, scale }
+    }
+
+    fn height_at(&self, u: f64, v: f64, p: &point3) -> f64 {
+        self.height.value(u, v, p).v[0] * self.scale
+    }
+
+    fn shade(&self, rec: &hit_record) -> hit_record {
+        // Objects without tangents get a frame of their own with 1 unit of uv per world unit
+        let (dpdu, dpdv) = if rec.tangent.near_zero() {
+            let frame = rec.shading_frame();
+            (frame.u, frame.v)
+        } else { (rec.tangent, rec.bitangent) };
+
+        // Forward differences, moving both uv and p so uv textures and solid textures both work
+        let du = 0.0005;
+        let (u, v) = (rec.uv.v[0], rec.uv.v[1]);
+        let h = self.height_at(u, v, &rec.p);
+        let dhdu = (self.height_at(u + du, v, &(rec.p + dpdu * du)) - h) / du;
+        let dhdv = (self.height_at(u, v + du, &(rec.p + dpdv * du)) - h) / du;
+
+        // Normal of the displaced surface p + n * h
+        let n = (dpdu + rec.n * dhdu).cross(&(dpdv + rec.n * dhdv));
+        let n = if n.dot(&rec.n) < 0. { n * -1. } else { n };
+        with_normal(rec, n)
+    }
+}
+
+impl Material for bump_mapped {
+    fn scatter(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.base.scatter(r, &self.shade(rec), attenuation, scatter)
+    }
+
+    fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.base.scatter_tex(r, &self.shade(rec), attenuation, scatter)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.base.emitted(u, v, p)
+    }
+
+    fn emitted_hit(&self, r: &ray, rec: &hit_record) -> colorRGB {
+        self.base.emitted_hit(r, rec)
+    }
+
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        self.base.eval(r, &self.shade(rec), scattered)
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        self.base.scattering_pdf(r, &self.shade(rec), scattered)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+}
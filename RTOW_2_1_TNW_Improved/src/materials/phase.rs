@@ -0,0 +1,138 @@
+use crate::materials::*;
+use crate::rtow_math::prelude::*;
+
+/// Angular distribution of the light scattered inside a medium
+/// cos is between the direction the ray travelled and the scattered one, positive means forward
+pub trait PhaseFunction: Send + Sync {
+    fn p(&self, cos: f64) -> f64;
+    /// Scattered direction for a ray that travelled along dir (unit), distributed as p()
+    fn sample(&self, dir: &vec3) -> vec3;
+}
+
+/// Direction at cos from dir with a random rotation around it
+fn around(dir: &vec3, cos: f64) -> vec3 {
+    let sin = (1. - cos * cos).max(0.).sqrt();
+    let phi = 2. * pi * rand_f64();
+    onb::build_from_w(dir).local(&vec3::from(sin * phi.cos(), sin * phi.sin(), cos))
+}
+
+pub struct isotropic_phase {}
+
+impl PhaseFunction for isotropic_phase {
+    fn p(&self, cos: f64) -> f64 { 1. / (4. * pi) }
+
+    fn sample(&self, dir: &vec3) -> vec3 {
+        around(dir, 1. - 2. * rand_f64())
+    }
+}
+
+/// g in (-1, 1): > 0 forward scattering (haze, clouds), < 0 back scattering, 0 isotropic
+pub struct henyey_greenstein {
+    g: f64,
+}
+
+impl henyey_greenstein {
+    pub fn from(g: f64) -> henyey_greenstein {
+        henyey_greenstein { g: g.clamp(-0.99, 0.99) }
+    }
+}
+
+impl PhaseFunction for henyey_greenstein {
+    fn p(&self, cos: f64) -> f64 {
+        let g2 = self.g * self.g;
+        let denom = 1. + g2 - 2. * self.g * cos;
+        (1. - g2) / (4. * pi * denom * denom.sqrt())
+    }
+
+    fn sample(&self, dir: &vec3) -> vec3 {
+        let g = self.g;
+        let xi = rand_f64();
+        let cos = if g.abs() < 1e-3 {
+            1. - 2. * xi
+        } else {
+            let sq = (1. - g * g) / (1. - g + 2. * g * xi);
+            (1. + g * g - sq * sq) / (2. * g)
+        };
+        around(dir, cos.clamp(-1., 1.))
+    }
+}
+
+/// Blend of 2 HG lobes, usually a strong forward one and a weaker back one (clouds)
+pub struct double_hg {
+    a: henyey_greenstein,
+    b: henyey_greenstein,
+    weight_a: f64,
+}
+
+impl double_hg {
+    pub fn from(g_a: f64, g_b: f64, weight_a: f64) -> double_hg {
+        double_hg { a: henyey_greenstein::from(g_a), b: henyey_greenstein::from(g_b), weight_a: weight_a.clamp(0., 1.) }
+    }
+}
+
+impl PhaseFunction for double_hg {
+    fn p(&self, cos: f64) -> f64 {
+        self.weight_a * self.a.p(cos) + (1. - self.weight_a) * self.b.p(cos)
+    }
+
+    fn sample(&self, dir: &vec3) -> vec3 {
+        if rand_f64() < self.weight_a { self.a.sample(dir) } else { self.b.sample(dir) }
+    }
+}
+
+/// Scattering by particles much smaller than the wavelength (clear sky)
+/// Only the angular shape, the blue tint comes from the texture of the medium
+pub struct rayleigh {}
+
+impl PhaseFunction for rayleigh {
+    fn p(&self, cos: f64) -> f64 {
+        3. / (16. * pi) * (1. + cos * cos)
+    }
+
+    fn sample(&self, dir: &vec3) -> vec3 {
+        // Inverting the cdf (cos^3 + 3cos + 4) / 8 = xi, Cardano's single real root
+        let q = 4. * rand_f64() - 2.;
+        let a = (q + (q * q + 1.).sqrt()).cbrt();
+        around(dir, (a - 1. / a).clamp(-1., 1.))
+    }
+}
+
+/// Material for media, scatters following its phase function with tex as albedo
+pub struct phase_material {
+    phase: Arc<dyn PhaseFunction>,
+    tex: Arc<dyn Texture>,
+}
+
+impl phase_material {
+    pub fn from(phase: Arc<dyn PhaseFunction>, tex: Arc<dyn Texture>) -> phase_material {
+        phase_material { phase, tex }
+    }
+
+    fn cos(r: &ray, scattered: &ray) -> f64 {
+        r.dir.unit_vec().dot(&scattered.dir.unit_vec())
+    }
+}
+
+impl Material for phase_material {
+    fn scatter(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        false
+    }
+
+    fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        *scatter = ray::from_t(rec.p, self.phase.sample(&r.dir.unit_vec()), r.time);
+        *attenuation = rec.texture(&self.tex);
+        true
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::new()
+    }
+
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        rec.texture(&self.tex) * self.phase.p(phase_material::cos(r, scattered))
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        self.phase.p(phase_material::cos(r, scattered))
+    }
+}
@@ -1,3 +1,10 @@
 pub use crate::materials::textures::*;
 pub use crate::materials::emissive::*;
+pub use crate::materials::phase::*;
+pub use crate::materials::microfacet::*;
+pub use crate::materials::principled_bsdf::*;
+pub use crate::materials::layered::*;
+pub use crate::materials::normal_maps::*;
+pub use crate::materials::procedural::*;
+pub use crate::materials::texture_nodes::*;
 pub use crate::materials::*;
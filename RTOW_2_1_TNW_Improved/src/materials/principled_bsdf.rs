@@ -0,0 +1,233 @@
+use crate::materials::*;
+use crate::materials::microfacet::*;
+use crate::rtow_math::prelude::*;
+
+/// Constant texture for the scalar parameters
+pub fn scalar_tex(v: f64) -> Arc<dyn Texture> {
+    Arc::new(Solid_Color::from(v, v, v))
+}
+
+/// Principled BSDF (Burley 2012/2015), one material covering plastic, metal, glass and everything between
+/// Every parameter is a texture, scalars read its first channel and are expected in [0, 1].
+/// Lobes: Burley diffuse + sheen, anisotropic GGX specular, GGX clearcoat and rough dielectric transmission.
+/// The fields are public so materials can be built with struct update syntax over principled::new()
+/// (there is no OBJ/MTL or scene loader in the tree yet, they would fill them the same way)
+pub struct principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>,
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub anisotropic: Arc<dyn Texture>,
+}
+
+/// Parameters read at a hit and what gets derived from them
+struct principled_lobes {
+    base: colorRGB,
+    roughness: f64,
+    alpha_x: f64,
+    alpha_y: f64,
+    spec0: colorRGB,
+    sheen: colorRGB,
+    // Weight of diffuse, specular, transmission and clearcoat
+    weights: [f64; 4],
+    // Probability of sampling each of them
+    probs: [f64; 4],
+    eta: f64,
+}
+
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const TRANSMISSION: usize = 2;
+const CLEARCOAT: usize = 3;
+
+// Clearcoat is always glossy, like a varnish
+const CLEARCOAT_ALPHA: f64 = 0.05;
+
+fn schlick_weight(cos: f64) -> f64 {
+    let m = (1. - cos).clamp(0., 1.);
+    m * m * m * m * m
+}
+
+fn luminance(c: &colorRGB) -> f64 {
+    0.2126 * c.v[0] + 0.7152 * c.v[1] + 0.0722 * c.v[2]
+}
+
+impl principled {
+    /// Dielectric with half roughness, the usual plastic look
+    pub fn new(base_color: Arc<dyn Texture>) -> principled {
+        principled {
+            base_color,
+            metallic: scalar_tex(0.),
+            roughness: scalar_tex(0.5),
+            specular: scalar_tex(0.5),
+            specular_tint: scalar_tex(0.),
+            sheen: scalar_tex(0.),
+            clearcoat: scalar_tex(0.),
+            transmission: scalar_tex(0.),
+            anisotropic: scalar_tex(0.),
+        }
+    }
+
+    fn lobes(&self, rec: &hit_record) -> principled_lobes {
+        let scalar = |t: &Arc<dyn Texture>| rec.texture(t).v[0].clamp(0., 1.);
+
+        let base = rec.texture(&self.base_color);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let specular = scalar(&self.specular);
+        let transmission = scalar(&self.transmission);
+        let clearcoat = scalar(&self.clearcoat);
+
+        // Hue of the base colour without its brightness
+        let lum = luminance(&base);
+        let tint = if lum > 0. { base / lum } else { colorRGB::one() };
+
+        let aspect = (1. - 0.9 * scalar(&self.anisotropic)).sqrt();
+        let alpha = roughness * roughness;
+
+        let spec_tint = scalar(&self.specular_tint);
+        let dielectric_spec0 = (colorRGB::one() * (1. - spec_tint) + tint * spec_tint) * (0.08 * specular);
+        let spec0 = dielectric_spec0 * (1. - metallic) + base * metallic;
+
+        let weights = [
+            (1. - metallic) * (1. - transmission),
+            1. - (1. - metallic) * transmission,
+            (1. - metallic) * transmission,
+            0.25 * clearcoat,
+        ];
+        let total: f64 = weights.iter().sum();
+        let probs = weights.map(|w| if total > 0. { w / total } else { 0. });
+
+        // Same ior as the reflectance at normal incidence the specular parameter gives
+        let sqrt_f0 = (0.08 * specular).sqrt().min(0.99);
+        let ior = (1. + sqrt_f0) / (1. - sqrt_f0);
+
+        principled_lobes {
+            base,
+            roughness,
+            alpha_x: (alpha / aspect).max(0.001),
+            alpha_y: (alpha * aspect).max(0.001),
+            spec0,
+            sheen: (colorRGB::one() * 0.5 + tint * 0.5) * scalar(&self.sheen),
+            weights,
+            probs,
+            eta: if rec.front_face { ior } else { 1. / ior },
+        }
+    }
+
+    /// (brdf * cos, pdf) adding up all the lobes
+    fn eval_pdf(l: &principled_lobes, wo: &vec3, wi: &vec3) -> (colorRGB, f64) {
+        let mut f = colorRGB::new();
+        let mut pdf = 0.;
+        if wo.v[2] <= 0. { return (f, pdf) }
+
+        if l.weights[TRANSMISSION] > 0. {
+            let (t_f, t_pdf) = dielectric_interface_eval_pdf(wo, wi, roughness_to_alpha(l.roughness), l.eta);
+            f = f + l.base * (t_f * l.weights[TRANSMISSION]);
+            pdf += t_pdf * l.probs[TRANSMISSION];
+        }
+
+        // The rest only reflect
+        if wi.v[2] <= 0. { return (f, pdf) }
+        let h = (*wo + *wi).unit_vec();
+        let cos_d = wi.dot(&h);
+
+        if l.weights[DIFFUSE] > 0. {
+            // Retro-reflection at grazing angles grows with roughness
+            let fd90 = 0.5 + 2. * l.roughness * cos_d * cos_d;
+            let diffuse = (1. + (fd90 - 1.) * schlick_weight(wi.v[2])) * (1. + (fd90 - 1.) * schlick_weight(wo.v[2])) / pi;
+            let sheen = l.sheen * schlick_weight(cos_d);
+            f = f + (l.base * diffuse + sheen) * (l.weights[DIFFUSE] * wi.v[2]);
+            pdf += wi.v[2] / pi * l.probs[DIFFUSE];
+        }
+
+        if l.weights[SPECULAR] > 0. {
+            let fresnel = l.spec0 + (colorRGB::one() - l.spec0) * schlick_weight(cos_d);
+            let d = ggx_d_aniso(&h, l.alpha_x, l.alpha_y);
+            let g2 = smith_g2_aniso(wo, wi, l.alpha_x, l.alpha_y);
+            f = f + fresnel * (d * g2 / (4. * wo.v[2]) * l.weights[SPECULAR]);
+            pdf += ggx_vndf_pdf_aniso(wo, &h, l.alpha_x, l.alpha_y) / (4. * wo.dot(&h)) * l.probs[SPECULAR];
+        }
+
+        if l.weights[CLEARCOAT] > 0. {
+            let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+            let d = ggx_d(&h, CLEARCOAT_ALPHA);
+            let g2 = smith_g2(wo, wi, CLEARCOAT_ALPHA);
+            f = f + colorRGB::one() * (fresnel * d * g2 / (4. * wo.v[2]) * l.weights[CLEARCOAT]);
+            pdf += ggx_vndf_pdf(wo, &h, CLEARCOAT_ALPHA) / (4. * wo.dot(&h)) * l.probs[CLEARCOAT];
+        }
+
+        (f, pdf)
+    }
+
+    fn local_dirs(r: &ray, rec: &hit_record, scattered: &ray) -> (vec3, vec3) {
+        // Anisotropy stretches along the tangent
+        let frame = rec.shading_frame();
+        (frame.to_local(&(r.dir.unit_vec() * -1.)), frame.to_local(&scattered.dir.unit_vec()))
+    }
+}
+
+impl Material for principled {
+    fn scatter(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        self.scatter_tex(r, rec, attenuation, scatter)
+    }
+
+    fn scatter_tex(&self, r: &ray, rec: &hit_record, attenuation: &mut colorRGB, scatter: &mut ray) -> bool {
+        let l = self.lobes(rec);
+        let frame = rec.shading_frame();
+        let wo = frame.to_local(&(r.dir.unit_vec() * -1.));
+        if wo.v[2] <= 0. { return false }
+
+        // Pick one lobe to sample from, then weigh by all of them (one sample MIS)
+        let mut pick = rand_f64();
+        let mut lobe = DIFFUSE;
+        for i in 0..4 {
+            lobe = i;
+            if pick < l.probs[i] { break }
+            pick -= l.probs[i];
+        }
+
+        let reflect = |h: vec3| {
+            let wi = h * (2. * wo.dot(&h)) - wo;
+            if wi.v[2] > 0. { Some(wi) } else { None }
+        };
+        let wi = match lobe {
+            DIFFUSE => {
+                let d = vec3::from(0., 0., 1.) + random_unit_vector();
+                if d.near_zero() { Some(vec3::from(0., 0., 1.)) } else { Some(d.unit_vec()) }
+            },
+            SPECULAR => reflect(sample_ggx_vndf_aniso(&wo, l.alpha_x, l.alpha_y)),
+            TRANSMISSION => sample_dielectric_interface(&wo, roughness_to_alpha(l.roughness), l.eta),
+            _ => reflect(sample_ggx_vndf(&wo, CLEARCOAT_ALPHA)),
+        };
+        let wi = match wi {
+            Some(wi) => wi,
+            None => return false,
+        };
+
+        let (f, pdf) = principled::eval_pdf(&l, &wo, &wi);
+        if pdf <= 0. { return false }
+
+        *attenuation = f / pdf;
+        *scatter = ray::from_t(rec.p, frame.local(&wi), r.time);
+        true
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::new()
+    }
+
+    fn eval(&self, r: &ray, rec: &hit_record, scattered: &ray) -> colorRGB {
+        let (wo, wi) = principled::local_dirs(r, rec, scattered);
+        principled::eval_pdf(&self.lobes(rec), &wo, &wi).0
+    }
+
+    fn scattering_pdf(&self, r: &ray, rec: &hit_record, scattered: &ray) -> f64 {
+        let (wo, wi) = principled::local_dirs(r, rec, scattered);
+        principled::eval_pdf(&self.lobes(rec), &wo, &wi).1
+    }
+}
@@ -0,0 +1,475 @@
+use crate::materials::textures::*;
+use crate::objects::hit::*;
+use crate::rtow_math::prelude::*;
+use std::sync::Arc;
+
+// Seeded procedural textures. The noise here hashes the lattice coordinates with the seed instead of
+// keeping permutation tables like Perlin_Noise, the same seed always gives the same pattern.
+// Scalar textures are grey in [0,1], a Color_Ramp turns them into colours.
+// Parameters are pub, tune them with ..X::new(seed)
+
+/// Well mixed 64 bits from a seed and a lattice point
+fn hash3(seed: u64, x: i64, y: i64, z: i64) -> u64 {
+    let mut h = seed ^ 0x9E3779B97F4A7C15;
+    for c in [x, y, z] {
+        h = (h ^ c as u64).wrapping_mul(0xBF58476D1CE4E5B9);
+        h ^= h >> 29;
+    }
+    h = h.wrapping_mul(0x94D049BB133111EB);
+    h ^ (h >> 32)
+}
+
+/// Uniform in [0, 1) from a hash
+fn hash_f64(h: u64) -> f64 {
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn lattice(p: &point3) -> (i64, i64, i64) {
+    (p.v[0].floor() as i64, p.v[1].floor() as i64, p.v[2].floor() as i64)
+}
+
+// ----------------------------------------------------------------
+// Base noises
+
+/// 3D simplex noise (Perlin 2001, as written by Gustavson), roughly in [-1, 1]
+/// Cheaper than Perlin_Noise in 3D and without its axis aligned artifacts
+pub fn simplex3(seed: u64, p: &point3) -> f64 {
+    const F3: f64 = 1. / 3.;
+    const G3: f64 = 1. / 6.;
+    // The 12 edges of a cube as gradients
+    const GRADS: [[f64; 3]; 12] = [
+        [1., 1., 0.], [-1., 1., 0.], [1., -1., 0.], [-1., -1., 0.],
+        [1., 0., 1.], [-1., 0., 1.], [1., 0., -1.], [-1., 0., -1.],
+        [0., 1., 1.], [0., -1., 1.], [0., 1., -1.], [0., -1., -1.],
+    ];
+
+    // Skew into the simplex grid and find the cell
+    let s = (p.v[0] + p.v[1] + p.v[2]) * F3;
+    let (i, j, k) = lattice(&(*p + vec3::from(s, s, s)));
+    let t = (i + j + k) as f64 * G3;
+    let x0 = *p - vec3::from(i as f64 - t, j as f64 - t, k as f64 - t);
+
+    // Which of the 6 tetrahedra of the cube the point is in, by the order of its coordinates
+    let (a, b, c) = (x0.v[0], x0.v[1], x0.v[2]);
+    let (o1, o2) = if a >= b {
+        if b >= c { ([1, 0, 0], [1, 1, 0]) } else if a >= c { ([1, 0, 0], [1, 0, 1]) } else { ([0, 0, 1], [1, 0, 1]) }
+    } else {
+        if b < c { ([0, 0, 1], [0, 1, 1]) } else if a < c { ([0, 1, 0], [0, 1, 1]) } else { ([0, 1, 0], [1, 1, 0]) }
+    };
+
+    let mut sum = 0.;
+    for (n, o) in [[0, 0, 0], o1, o2, [1, 1, 1]].iter().enumerate() {
+        let d = x0 - vec3::from(o[0] as f64, o[1] as f64, o[2] as f64) + vec3::from(1., 1., 1.) * (n as f64 * G3);
+        let falloff = 0.6 - d.length_squared();
+        if falloff <= 0. { continue }
+        let g = GRADS[(hash3(seed, i + o[0], j + o[1], k + o[2]) % 12) as usize];
+        sum += falloff.powi(4) * (g[0] * d.v[0] + g[1] * d.v[1] + g[2] * d.v[2]);
+    }
+    32. * sum
+}
+
+/// Closest and second closest feature point distances of cellular noise, and the cell of the closest
+pub struct worley_sample {
+    pub f1: f64,
+    pub f2: f64,
+    pub cell: (i64, i64, i64),
+}
+
+/// Worley (cellular) noise, one feature point per unit cell moved up to jitter from its corner
+pub fn worley3(seed: u64, p: &point3, jitter: f64) -> worley_sample {
+    let (ci, cj, ck) = lattice(p);
+    let mut ret = worley_sample { f1: INFINITY, f2: INFINITY, cell: (ci, cj, ck) };
+    for i in ci - 1..=ci + 1 {
+        for j in cj - 1..=cj + 1 {
+            for k in ck - 1..=ck + 1 {
+                let h = hash3(seed, i, j, k);
+                let offset = vec3::from(hash_f64(h), hash_f64(h.rotate_left(21)), hash_f64(h.rotate_left(42)));
+                let feature = vec3::from(i as f64, j as f64, k as f64) + vec3::from(0.5, 0.5, 0.5) + (offset - vec3::from(0.5, 0.5, 0.5)) * jitter;
+                let dist = (feature - *p).length();
+                if dist < ret.f1 {
+                    ret.f2 = ret.f1;
+                    ret.f1 = dist;
+                    ret.cell = (i, j, k);
+                } else if dist < ret.f2 {
+                    ret.f2 = dist;
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// Fractal brownian motion: octaves of simplex noise, each lacunarity times finer and gain times weaker
+/// Roughly in [-1, 1]
+pub fn fbm3(seed: u64, p: &point3, octaves: i32, lacunarity: f64, gain: f64) -> f64 {
+    let (mut sum, mut norm) = (0., 0.);
+    let (mut amp, mut freq) = (1., 1.);
+    for i in 0..octaves {
+        sum += amp * simplex3(seed.wrapping_add(i as u64), &(*p * freq));
+        norm += amp;
+        amp *= gain;
+        freq *= lacunarity;
+    }
+    if norm > 0. { sum / norm } else { 0. }
+}
+
+// ----------------------------------------------------------------
+// Scalar textures
+
+/// Simplex noise remapped to [0,1]
+pub struct Simplex_Noise {
+    pub seed: u64,
+    pub scale: f64,
+}
+
+impl Simplex_Noise {
+    pub fn new(seed: u64) -> Simplex_Noise {
+        Simplex_Noise { seed, scale: 1. }
+    }
+}
+
+impl Texture for Simplex_Noise {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::one() * (0.5 + 0.5 * simplex3(self.seed, &(*p * self.scale))).clamp(0., 1.)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WorleyMode {
+    // Distance to the closest point: round cells, dark at their center
+    F1,
+    // Distance to the second closest point
+    F2,
+    // Difference between both: dark lines along the cell borders
+    Edges,
+}
+
+/// Cellular noise, distances in cell units clamped to [0,1]
+pub struct Worley_Noise {
+    pub seed: u64,
+    pub scale: f64,
+    // 0 gives a regular grid, 1 fully random points
+    pub jitter: f64,
+    pub mode: WorleyMode,
+}
+
+impl Worley_Noise {
+    pub fn new(seed: u64) -> Worley_Noise {
+        Worley_Noise { seed, scale: 1., jitter: 1., mode: WorleyMode::F1 }
+    }
+}
+
+impl Texture for Worley_Noise {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        let w = worley3(self.seed, &(*p * self.scale), self.jitter);
+        let d = match self.mode {
+            WorleyMode::F1 => w.f1,
+            WorleyMode::F2 => w.f2,
+            WorleyMode::Edges => w.f2 - w.f1,
+        };
+        colorRGB::one() * d.clamp(0., 1.)
+    }
+}
+
+/// fBm of simplex noise in [0,1], octaves below the pixel size are left out when filtering
+pub struct Fbm_Noise {
+    pub seed: u64,
+    pub scale: f64,
+    pub octaves: i32,
+    pub lacunarity: f64,
+    pub gain: f64,
+}
+
+impl Fbm_Noise {
+    pub fn new(seed: u64) -> Fbm_Noise {
+        Fbm_Noise { seed, scale: 1., octaves: 6, lacunarity: 2., gain: 0.5 }
+    }
+
+    fn at(&self, p: &point3, octaves: i32) -> f64 {
+        (0.5 + 0.5 * fbm3(self.seed, &(*p * self.scale), octaves, self.lacunarity, self.gain)).clamp(0., 1.)
+    }
+
+    fn octaves_for(&self, footprint: &tex_footprint) -> i32 {
+        visible_octaves_by(footprint.world_width() * self.scale, self.octaves, self.lacunarity)
+    }
+}
+
+impl Texture for Fbm_Noise {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::one() * self.at(p, self.octaves)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        colorRGB::one() * self.at(p, self.octaves_for(footprint))
+    }
+}
+
+/// Same as visible_octaves for any lacunarity
+fn visible_octaves_by(width: f64, octaves: i32, lacunarity: f64) -> i32 {
+    if width <= 0. || lacunarity <= 1. { return octaves }
+    (((1. / width).ln() / lacunarity.ln()).floor() as i32 + 1).max(1).min(octaves)
+}
+
+/// Ridged multifractal (Musgrave): sharp crests where the noise crosses 0, rougher on high ground.
+/// Mountain ranges, lightning, veins. Roughly in [0,1]
+pub struct Ridged_Noise {
+    pub seed: u64,
+    pub scale: f64,
+    pub octaves: i32,
+    pub lacunarity: f64,
+    // How much each ridge feeds the detail of the next octave
+    pub gain: f64,
+    // Raises the ridges, 1 is the usual value
+    pub offset: f64,
+    // Fractal increment, higher is smoother
+    pub h: f64,
+}
+
+impl Ridged_Noise {
+    pub fn new(seed: u64) -> Ridged_Noise {
+        Ridged_Noise { seed, scale: 1., octaves: 6, lacunarity: 2., gain: 2., offset: 1., h: 1. }
+    }
+
+    fn at(&self, p: &point3, octaves: i32) -> f64 {
+        let mut q = *p * self.scale;
+        let mut signal = self.offset - simplex3(self.seed, &q).abs();
+        signal *= signal;
+        let (mut sum, mut norm) = (signal, self.offset * self.offset);
+
+        for i in 1..octaves {
+            q = q * self.lacunarity;
+            let weight = (signal * self.gain).clamp(0., 1.);
+            signal = self.offset - simplex3(self.seed.wrapping_add(i as u64), &q).abs();
+            signal = signal * signal * weight;
+            let amp = self.lacunarity.powf(-self.h * i as f64);
+            sum += signal * amp;
+            norm += self.offset * self.offset * amp;
+        }
+        (sum / norm).clamp(0., 1.)
+    }
+}
+
+impl Texture for Ridged_Noise {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::one() * self.at(p, self.octaves)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        let octaves = visible_octaves_by(footprint.world_width() * self.scale, self.octaves, self.lacunarity);
+        colorRGB::one() * self.at(p, octaves)
+    }
+}
+
+/// Veins: bands along dir pushed around by fBm. Scalar, use Color_Ramp::marble for colours
+pub struct Marble_Tex {
+    pub noise: Fbm_Noise,
+    // Bands per unit along dir
+    pub frequency: f64,
+    pub dir: vec3,
+    // How much the fbm bends the bands
+    pub turbulence: f64,
+}
+
+impl Marble_Tex {
+    pub fn new(seed: u64) -> Marble_Tex {
+        Marble_Tex { noise: Fbm_Noise::new(seed), frequency: 1., dir: vec3::from(1., 0., 0.), turbulence: 1.5 }
+    }
+
+    fn at(&self, p: &point3, octaves: i32) -> f64 {
+        let phase = self.frequency * p.dot(&self.dir) + self.turbulence * fbm3(self.noise.seed, &(*p * self.noise.scale), octaves, self.noise.lacunarity, self.noise.gain);
+        0.5 + 0.5 * (2. * pi * phase).sin()
+    }
+}
+
+impl Texture for Marble_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::one() * self.at(p, self.noise.octaves)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        // Bands thinner than the pixel fade to their average
+        let fade = ((footprint.world_width() * self.frequency - 0.5) / 1.5).clamp(0., 1.);
+        colorRGB::one() * (self.at(p, self.noise.octaves_for(footprint)) * (1. - fade) + 0.5 * fade)
+    }
+}
+
+/// Growth rings around an axis through center, wobbled by fBm. Scalar, use Color_Ramp::wood for colours
+pub struct Wood_Tex {
+    pub noise: Fbm_Noise,
+    pub center: point3,
+    pub axis: vec3,
+    // Rings per unit of radius
+    pub rings: f64,
+    pub turbulence: f64,
+}
+
+impl Wood_Tex {
+    pub fn new(seed: u64) -> Wood_Tex {
+        Wood_Tex { noise: Fbm_Noise::new(seed), center: point3::new(), axis: vec3::from(0., 1., 0.), rings: 4., turbulence: 0.1 }
+    }
+
+    fn at(&self, p: &point3, octaves: i32) -> f64 {
+        let axis = self.axis.unit_vec();
+        let rel = *p - self.center;
+        let radius = (rel - axis * rel.dot(&axis)).length();
+        let wobble = self.turbulence * fbm3(self.noise.seed, &(*p * self.noise.scale), octaves, self.noise.lacunarity, self.noise.gain);
+        let ring = ((radius + wobble) * self.rings).rem_euclid(1.);
+        // Early wood grows slow and light, late wood is a thin dark band
+        ring.powf(3.)
+    }
+}
+
+impl Texture for Wood_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::one() * self.at(p, self.noise.octaves)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        // Average of ring^3 over a ring is 1/4
+        let fade = ((footprint.world_width() * self.rings - 0.5) / 1.5).clamp(0., 1.);
+        colorRGB::one() * (self.at(p, self.noise.octaves_for(footprint)) * (1. - fade) + 0.25 * fade)
+    }
+}
+
+// ----------------------------------------------------------------
+// Coloured textures
+
+/// Running bond brick wall in uv (rects, quads, planes), every other row shifted half a brick
+pub struct Brick_Tex {
+    pub seed: u64,
+    pub brick: colorRGB,
+    pub mortar: colorRGB,
+    // Bricks per unit of u and rows per unit of v
+    pub bricks_u: f64,
+    pub rows_v: f64,
+    // Mortar width as a fraction of a brick height
+    pub mortar_width: f64,
+    // Random brightness change of each brick, 0 for all the same
+    pub variation: f64,
+}
+
+impl Brick_Tex {
+    pub fn new(seed: u64) -> Brick_Tex {
+        Brick_Tex {
+            seed,
+            brick: colorRGB::from(0.55, 0.2, 0.12),
+            mortar: colorRGB::from(0.75, 0.72, 0.68),
+            bricks_u: 4.,
+            rows_v: 12.,
+            mortar_width: 0.1,
+            variation: 0.3,
+        }
+    }
+}
+
+impl Texture for Brick_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        let y = v * self.rows_v;
+        let row = y.floor();
+        let x = u * self.bricks_u + if (row as i64).rem_euclid(2) == 1 { 0.5 } else { 0. };
+        let col = x.floor();
+
+        // Mortar measured in brick heights, bricks are 1/rows_v tall and 1/bricks_u wide
+        let aspect = self.rows_v / self.bricks_u;
+        let (fx, fy) = ((x - col) * aspect, y - row);
+        let half = 0.5 * self.mortar_width;
+        if fy < half || fy > 1. - half || fx < half || fx > aspect - half { return self.mortar }
+
+        let shade = 1. + self.variation * (hash_f64(hash3(self.seed, col as i64, row as i64, 0)) - 0.5);
+        self.brick * shade
+    }
+}
+
+/// Voronoi cells, each with a random colour between a and b, border colour near the cell edges
+pub struct Voronoi_Tex {
+    pub seed: u64,
+    pub scale: f64,
+    pub jitter: f64,
+    pub a: colorRGB,
+    pub b: colorRGB,
+    // Width of the borders in cell units, 0 for none
+    pub border: f64,
+    pub border_color: colorRGB,
+}
+
+impl Voronoi_Tex {
+    pub fn new(seed: u64) -> Voronoi_Tex {
+        Voronoi_Tex {
+            seed, scale: 1., jitter: 1.,
+            a: colorRGB::new(), b: colorRGB::one(),
+            border: 0., border_color: colorRGB::new(),
+        }
+    }
+}
+
+impl Texture for Voronoi_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        let w = worley3(self.seed, &(*p * self.scale), self.jitter);
+        // Half the F2 - F1 gap is about the distance to the edge
+        if 0.5 * (w.f2 - w.f1) < self.border { return self.border_color }
+        let t = hash_f64(hash3(self.seed ^ 0x5EED, w.cell.0, w.cell.1, w.cell.2));
+        self.a * (1. - t) + self.b * t
+    }
+}
+
+/// Maps the first channel of input to a colour, linear between stops (position, colour)
+pub struct Color_Ramp {
+    pub input: Arc<dyn Texture>,
+    stops: Vec<(f64, colorRGB)>,
+}
+
+impl Color_Ramp {
+    pub fn from(input: Arc<dyn Texture>, mut stops: Vec<(f64, colorRGB)>) -> Color_Ramp {
+        if stops.is_empty() { panic!("Color_Ramp needs at least one stop") }
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Color_Ramp { input, stops }
+    }
+
+    /// Black to white, handy to check a scalar texture
+    pub fn grey(input: Arc<dyn Texture>) -> Color_Ramp {
+        Color_Ramp::from(input, vec![(0., colorRGB::new()), (1., colorRGB::one())])
+    }
+
+    pub fn marble(seed: u64) -> Color_Ramp {
+        Color_Ramp::from(Arc::new(Marble_Tex::new(seed)), vec![
+            (0., colorRGB::from(0.3, 0.3, 0.33)),
+            (0.25, colorRGB::from(0.8, 0.8, 0.78)),
+            (1., colorRGB::from(0.95, 0.94, 0.92)),
+        ])
+    }
+
+    pub fn wood(seed: u64) -> Color_Ramp {
+        Color_Ramp::from(Arc::new(Wood_Tex::new(seed)), vec![
+            (0., colorRGB::from(0.6, 0.4, 0.22)),
+            (0.6, colorRGB::from(0.5, 0.3, 0.15)),
+            (1., colorRGB::from(0.25, 0.13, 0.06)),
+        ])
+    }
+
+    pub fn map(&self, t: f64) -> colorRGB {
+        let first = self.stops[0];
+        if t <= first.0 { return first.1 }
+        for pair in self.stops.windows(2) {
+            let ((t0, c0), (t1, c1)) = (pair[0], pair[1]);
+            if t <= t1 {
+                let s = if t1 > t0 { (t - t0) / (t1 - t0) } else { 1. };
+                return c0 * (1. - s) + c1 * s;
+            }
+        }
+        self.stops[self.stops.len() - 1].1
+    }
+}
+
+impl Texture for Color_Ramp {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.map(self.input.value(u, v, p).v[0])
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        self.map(self.input.value_filtered(u, v, p, footprint).v[0])
+    }
+
+    fn value_hit(&self, rec: &hit_record) -> colorRGB {
+        self.map(self.input.value_hit(rec).v[0])
+    }
+}
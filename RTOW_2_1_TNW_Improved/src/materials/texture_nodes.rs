@@ -0,0 +1,338 @@
+use crate::materials::textures::*;
+use crate::objects::hit::*;
+use crate::rtow_math::prelude::*;
+use std::sync::Arc;
+
+// Textures made of other textures, so looks like a noise modulated checker are built by
+// plugging nodes together instead of writing a new Texture. Every node passes value, value_filtered
+// and value_hit down to its inputs, filtering and the normal reach the leaves through any graph.
+// Color_Ramp (procedural.rs) is the ramp node, scalars are read from the first channel.
+
+/// a where the first channel of mask is 0, b where it is 1, blended in between
+pub struct Mix_Tex {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+    pub mask: Arc<dyn Texture>,
+}
+
+impl Mix_Tex {
+    pub fn from(a: Arc<dyn Texture>, b: Arc<dyn Texture>, mask: Arc<dyn Texture>) -> Mix_Tex {
+        Mix_Tex { a, b, mask }
+    }
+
+    fn mix(a: colorRGB, b: colorRGB, mask: colorRGB) -> colorRGB {
+        let m = mask.v[0].clamp(0., 1.);
+        a * (1. - m) + b * m
+    }
+}
+
+impl Texture for Mix_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        Mix_Tex::mix(self.a.value(u, v, p), self.b.value(u, v, p), self.mask.value(u, v, p))
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        Mix_Tex::mix(self.a.value_filtered(u, v, p, footprint), self.b.value_filtered(u, v, p, footprint), self.mask.value_filtered(u, v, p, footprint))
+    }
+
+    fn value_hit(&self, rec: &hit_record) -> colorRGB {
+        Mix_Tex::mix(self.a.value_hit(rec), self.b.value_hit(rec), self.mask.value_hit(rec))
+    }
+}
+
+/// a * b per channel, a Solid_Color as b scales or tints a
+pub struct Multiply_Tex {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+}
+
+impl Multiply_Tex {
+    pub fn from(a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> Multiply_Tex {
+        Multiply_Tex { a, b }
+    }
+}
+
+impl Texture for Multiply_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.a.value(u, v, p) * self.b.value(u, v, p)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        self.a.value_filtered(u, v, p, footprint) * self.b.value_filtered(u, v, p, footprint)
+    }
+
+    fn value_hit(&self, rec: &hit_record) -> colorRGB {
+        self.a.value_hit(rec) * self.b.value_hit(rec)
+    }
+}
+
+/// a + b per channel, not clamped
+pub struct Add_Tex {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+}
+
+impl Add_Tex {
+    pub fn from(a: Arc<dyn Texture>, b: Arc<dyn Texture>) -> Add_Tex {
+        Add_Tex { a, b }
+    }
+}
+
+impl Texture for Add_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.a.value(u, v, p) + self.b.value(u, v, p)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        self.a.value_filtered(u, v, p, footprint) + self.b.value_filtered(u, v, p, footprint)
+    }
+
+    fn value_hit(&self, rec: &hit_record) -> colorRGB {
+        self.a.value_hit(rec) + self.b.value_hit(rec)
+    }
+}
+
+/// 1 - input per channel
+pub struct Invert_Tex {
+    pub input: Arc<dyn Texture>,
+}
+
+impl Invert_Tex {
+    pub fn from(input: Arc<dyn Texture>) -> Invert_Tex {
+        Invert_Tex { input }
+    }
+}
+
+impl Texture for Invert_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::one() - self.input.value(u, v, p)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        colorRGB::one() - self.input.value_filtered(u, v, p, footprint)
+    }
+
+    fn value_hit(&self, rec: &hit_record) -> colorRGB {
+        colorRGB::one() - self.input.value_hit(rec)
+    }
+}
+
+/// Maps [in_min, in_max] to [out_min, out_max] per channel, contrast and levels
+pub struct Remap_Tex {
+    pub input: Arc<dyn Texture>,
+    pub in_min: f64,
+    pub in_max: f64,
+    pub out_min: f64,
+    pub out_max: f64,
+    // Keep the result inside [out_min, out_max]
+    pub clamp: bool,
+}
+
+impl Remap_Tex {
+    pub fn from(input: Arc<dyn Texture>, in_min: f64, in_max: f64, out_min: f64, out_max: f64) -> Remap_Tex {
+        Remap_Tex { input, in_min, in_max, out_min, out_max, clamp: true }
+    }
+
+    fn remap(&self, c: colorRGB) -> colorRGB {
+        let mut ret = colorRGB::new();
+        for i in 0..3 {
+            let mut t = if self.in_max != self.in_min { (c.v[i] - self.in_min) / (self.in_max - self.in_min) } else { 0. };
+            if self.clamp { t = t.clamp(0., 1.) }
+            ret.v[i] = self.out_min + t * (self.out_max - self.out_min);
+        }
+        ret
+    }
+}
+
+impl Texture for Remap_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.remap(self.input.value(u, v, p))
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        self.remap(self.input.value_filtered(u, v, p, footprint))
+    }
+
+    fn value_hit(&self, rec: &hit_record) -> colorRGB {
+        self.remap(self.input.value_hit(rec))
+    }
+}
+
+/// Scales, rotates (degrees, around uv 0,0) and then offsets the uv the input sees
+pub struct Uv_Transform {
+    pub input: Arc<dyn Texture>,
+    pub scale: vec2,
+    pub rotation: f64,
+    pub offset: vec2,
+}
+
+impl Uv_Transform {
+    pub fn from(input: Arc<dyn Texture>, scale: vec2, rotation: f64, offset: vec2) -> Uv_Transform {
+        Uv_Transform { input, scale, rotation, offset }
+    }
+
+    /// Repeats the input n times in u and m times in v
+    pub fn tiled(input: Arc<dyn Texture>, n: f64, m: f64) -> Uv_Transform {
+        Uv_Transform::from(input, vec2::from(n, m), 0., vec2::new())
+    }
+
+    /// Linear part only, for the footprint derivatives
+    fn apply_linear(&self, u: f64, v: f64) -> (f64, f64) {
+        let (s, c) = deg_to_rad(self.rotation).sin_cos();
+        let (su, sv) = (u * self.scale.v[0], v * self.scale.v[1]);
+        (c * su - s * sv, s * su + c * sv)
+    }
+
+    fn apply(&self, u: f64, v: f64) -> (f64, f64) {
+        let (tu, tv) = self.apply_linear(u, v);
+        (tu + self.offset.v[0], tv + self.offset.v[1])
+    }
+
+    fn footprint(&self, footprint: &tex_footprint) -> tex_footprint {
+        let (dudx, dvdx) = self.apply_linear(footprint.dudx, footprint.dvdx);
+        let (dudy, dvdy) = self.apply_linear(footprint.dudy, footprint.dvdy);
+        tex_footprint { dudx, dvdx, dudy, dvdy, ..*footprint }
+    }
+}
+
+impl Texture for Uv_Transform {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        let (tu, tv) = self.apply(u, v);
+        self.input.value(tu, tv, p)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        let (tu, tv) = self.apply(u, v);
+        self.input.value_filtered(tu, tv, p, &self.footprint(footprint))
+    }
+
+    fn value_hit(&self, rec: &hit_record) -> colorRGB {
+        let mut moved = rec.clone();
+        let (tu, tv) = self.apply(rec.uv.v[0], rec.uv.v[1]);
+        moved.uv = point2::from(tu, tv);
+        moved.footprint = self.footprint(&rec.footprint);
+        self.input.value_hit(&moved)
+    }
+}
+
+/// Projects a uv texture along the three world axes and blends them by the normal, for objects
+/// without uvs (volumes, heightfields, boxes) or with stretched ones. Sharpness narrows the blend
+/// Without a hit (value, value_filtered) the three projections are averaged
+pub struct Triplanar_Tex {
+    pub input: Arc<dyn Texture>,
+    // World units per uv unit
+    pub scale: f64,
+    pub sharpness: f64,
+}
+
+impl Triplanar_Tex {
+    pub fn from(input: Arc<dyn Texture>, scale: f64) -> Triplanar_Tex {
+        Triplanar_Tex { input, scale, sharpness: 4. }
+    }
+
+    /// uv of p seen along axis, as the two other coordinates
+    fn project(&self, p: &vec3, axis: usize) -> (f64, f64) {
+        let (a, b) = match axis {
+            0 => (p.v[2], p.v[1]),
+            1 => (p.v[0], p.v[2]),
+            _ => (p.v[0], p.v[1]),
+        };
+        (a / self.scale, b / self.scale)
+    }
+
+    fn projected_footprint(&self, footprint: &tex_footprint, axis: usize) -> tex_footprint {
+        let (dudx, dvdx) = self.project(&footprint.dpdx, axis);
+        let (dudy, dvdy) = self.project(&footprint.dpdy, axis);
+        tex_footprint { dudx, dvdx, dudy, dvdy, ..*footprint }
+    }
+
+    fn weights(&self, n: &vec3) -> [f64; 3] {
+        let w = [n.v[0].abs().powf(self.sharpness), n.v[1].abs().powf(self.sharpness), n.v[2].abs().powf(self.sharpness)];
+        let sum = w[0] + w[1] + w[2];
+        if sum > 0. { [w[0] / sum, w[1] / sum, w[2] / sum] } else { [1. / 3.; 3] }
+    }
+}
+
+impl Texture for Triplanar_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.value_filtered(u, v, p, &tex_footprint::new())
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        let mut sum = colorRGB::new();
+        for axis in 0..3 {
+            let (pu, pv) = self.project(p, axis);
+            sum = sum + self.input.value_filtered(pu, pv, p, &self.projected_footprint(footprint, axis));
+        }
+        sum / 3.
+    }
+
+    fn value_hit(&self, rec: &hit_record) -> colorRGB {
+        let weights = self.weights(&rec.n);
+        let mut projected = rec.clone();
+        let mut sum = colorRGB::new();
+        for axis in 0..3 {
+            // Projections seen edge on add almost nothing, skip them
+            if weights[axis] < 0.001 { continue }
+            let (pu, pv) = self.project(&rec.p, axis);
+            projected.uv = point2::from(pu, pv);
+            projected.footprint = self.projected_footprint(&rec.footprint, axis);
+            sum = sum + self.input.value_hit(&projected) * weights[axis];
+        }
+        sum
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GeometrySource {
+    Position,
+    // Outward normal of the surface, whichever side was hit
+    Normal,
+    Uv,
+}
+
+/// Reads the hit itself as a texture: the position, normal or uv, either as rgb = xyz or as a
+/// grey level along an axis. Drives other nodes: snow on what faces up with
+/// Mix_Tex(rock, snow, Remap_Tex(Geometry_Tex::along(Normal, up), 0.6, 0.8, 0., 1.)), height gradients...
+/// Without a hit there is no normal, Normal gives black there
+pub struct Geometry_Tex {
+    pub source: GeometrySource,
+    // None for rgb = xyz, otherwise the dot product with it
+    pub axis: Option<vec3>,
+}
+
+impl Geometry_Tex {
+    pub fn from(source: GeometrySource) -> Geometry_Tex {
+        Geometry_Tex { source, axis: None }
+    }
+
+    pub fn along(source: GeometrySource, axis: vec3) -> Geometry_Tex {
+        Geometry_Tex { source, axis: Some(axis) }
+    }
+
+    fn output(&self, v: vec3) -> colorRGB {
+        match self.axis {
+            Some(axis) => colorRGB::one() * v.dot(&axis),
+            None => v,
+        }
+    }
+
+    fn lookup(&self, u: f64, v: f64, p: &point3, n: vec3) -> colorRGB {
+        match self.source {
+            GeometrySource::Position => self.output(*p),
+            GeometrySource::Normal => self.output(n),
+            GeometrySource::Uv => self.output(vec3::from(u, v, 0.)),
+        }
+    }
+}
+
+impl Texture for Geometry_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.lookup(u, v, p, vec3::new())
+    }
+
+    fn value_hit(&self, rec: &hit_record) -> colorRGB {
+        let outward = if rec.front_face { rec.n } else { rec.n * -1. };
+        self.lookup(rec.uv.v[0], rec.uv.v[1], &rec.p, outward)
+    }
+}
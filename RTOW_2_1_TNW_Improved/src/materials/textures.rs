@@ -1,6 +1,57 @@
 use crate::rtow_math::vec3::*;
+use crate::objects::hit::*;
 pub trait Texture {
     fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB;
+
+    /// Average over the area a pixel covers around the hit, textures that can't filter point sample
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        self.value(u, v, p)
+    }
+
+    /// What hit_record::texture() calls, override for textures that need more of the hit than
+    /// uv and p (the normal for triplanar projection). The default is the filtered lookup
+    fn value_hit(&self, rec: &hit_record) -> colorRGB {
+        self.value_filtered(rec.uv.v[0], rec.uv.v[1], &rec.p, &rec.footprint)
+    }
+}
+
+/// How far p and uv move to the next pixel in x and y, from the ray differentials
+/// All zero when the ray had none, textures then take a single point sample
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct tex_footprint {
+    pub dpdx: vec3,
+    pub dpdy: vec3,
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
+}
+
+impl tex_footprint {
+    pub fn new() -> tex_footprint {
+        tex_footprint { dpdx: vec3::new(), dpdy: vec3::new(), dudx: 0., dvdx: 0., dudy: 0., dvdy: 0. }
+    }
+
+    pub fn is_point(&self) -> bool {
+        self.dpdx.near_zero() && self.dpdy.near_zero() && self.dudx == 0. && self.dvdx == 0. && self.dudy == 0. && self.dvdy == 0.
+    }
+
+    /// Size of the footprint in world units, for solid textures
+    pub fn world_width(&self) -> f64 {
+        self.dpdx.length().max(self.dpdy.length())
+    }
+
+    /// Point at (sx, sy) pixels away, in [-0.5, 0.5] for the pixel itself
+    pub fn offset(&self, u: f64, v: f64, p: &point3, sx: f64, sy: f64) -> (f64, f64, point3) {
+        (u + self.dudx * sx + self.dudy * sy, v + self.dvdx * sx + self.dvdy * sy, *p + self.dpdx * sx + self.dpdy * sy)
+    }
+}
+
+/// Octaves of turbulence that are still bigger than the footprint, the rest would only alias
+/// width is the footprint in the units of the noise lattice
+fn visible_octaves(width: f64, depth: i32) -> i32 {
+    if width <= 0. { return depth }
+    ((1. / width).log2().floor() as i32 + 1).clamp(1, depth)
 }
 
 pub struct Solid_Color {
@@ -28,22 +79,90 @@ impl Texture for Solid_Color {
 }
 
 // -----------------------------------------------------------------
+/// Solid checker of the world position, cells are pi / scale wide. See Uv_Checkerboard_Tex for one
+/// that follows the surface
 pub struct Checkerboard_Tex {
-    odd: colorRGB,
-    even: colorRGB,
+    pub odd: colorRGB,
+    pub even: colorRGB,
+    pub scale: f64,
 }
 
 impl Checkerboard_Tex {
     pub fn new() -> Checkerboard_Tex {
-        Checkerboard_Tex { odd: colorRGB::from(0.,0.,0.), even: colorRGB::from(1.,1.,1.) }
+        Checkerboard_Tex::from(colorRGB::from(0.,0.,0.), colorRGB::from(1.,1.,1.), 10.)
+    }
+
+    pub fn from(odd: colorRGB, even: colorRGB, scale: f64) -> Checkerboard_Tex {
+        Checkerboard_Tex { odd, even, scale }
     }
 }
 
 impl Texture for Checkerboard_Tex {
     fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
-        let sines = (p.z() * 10.).sin() * (p.y() * 10.).sin() * (p.x() * 10.).sin();
+        let sines = (p.z() * self.scale).sin() * (p.y() * self.scale).sin() * (p.x() * self.scale).sin();
         if sines < 0. { self.odd } else { self.even }
     }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        if footprint.is_point() { return self.value(u, v, p) }
+
+        // 2x2 samples over the pixel, once it covers a couple of cells only their average is left
+        let mut sum = colorRGB::new();
+        for (sx, sy) in [(-0.25, -0.25), (0.25, -0.25), (-0.25, 0.25), (0.25, 0.25)] {
+            let (su, sv, sp) = footprint.offset(u, v, p, sx, sy);
+            sum = sum + self.value(su, sv, &sp);
+        }
+        let cell = std::f64::consts::PI / self.scale;
+        let fade = ((footprint.world_width() / cell - 0.5) / 1.5).clamp(0., 1.);
+        sum * (0.25 * (1. - fade)) + (self.odd + self.even) * (0.5 * fade)
+    }
+}
+
+/// Checker in uv space, cells_u by cells_v cells over [0,1] so it follows the surface of each object
+pub struct Uv_Checkerboard_Tex {
+    pub odd: colorRGB,
+    pub even: colorRGB,
+    pub cells_u: f64,
+    pub cells_v: f64,
+}
+
+impl Uv_Checkerboard_Tex {
+    pub fn new() -> Uv_Checkerboard_Tex {
+        Uv_Checkerboard_Tex::from(colorRGB::from(0.,0.,0.), colorRGB::from(1.,1.,1.), 8., 8.)
+    }
+
+    pub fn from(odd: colorRGB, even: colorRGB, cells_u: f64, cells_v: f64) -> Uv_Checkerboard_Tex {
+        Uv_Checkerboard_Tex { odd, even, cells_u, cells_v }
+    }
+}
+
+/// Integral of a square wave that is 1 on the odd cells, from 0 to x
+fn checker_integral(x: f64) -> f64 {
+    let half = x / 2.;
+    half.floor() + 2. * (half - half.floor() - 0.5).max(0.)
+}
+
+/// Fraction of [x - width / 2, x + width / 2] covered by odd cells
+fn checker_coverage(x: f64, width: f64) -> f64 {
+    if width <= 0. { return if x.rem_euclid(2.) >= 1. { 1. } else { 0. } }
+    (checker_integral(x + width / 2.) - checker_integral(x - width / 2.)) / width
+}
+
+impl Texture for Uv_Checkerboard_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        self.value_filtered(u, v, p, &tex_footprint::new())
+    }
+
+    // Box filtered in closed form over the uv footprint, no aliasing at any distance
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        let width_u = (footprint.dudx.abs() + footprint.dudy.abs()) * self.cells_u;
+        let width_v = (footprint.dvdx.abs() + footprint.dvdy.abs()) * self.cells_v;
+        let odd_u = checker_coverage(u * self.cells_u, width_u);
+        let odd_v = checker_coverage(v * self.cells_v, width_v);
+        // Odd where exactly one of u and v is in an odd cell
+        let odd = odd_u + odd_v - 2. * odd_u * odd_v;
+        self.odd * odd + self.even * (1. - odd)
+    }
 }
 
 // ----------------------------------------------------------------
@@ -276,6 +395,39 @@ impl Texture for Perlin_Noise {
             //self.turbulent_noise(&scaled_p, 7)
             (0.5 * (1. + (self.scale*p.v[2] + self.turbulent_noise(p, 7)*10.)).sin())
     }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        let width = footprint.world_width();
+        // Same as value() with the octaves the pixel can show
+        let marble = 0.5 * (1. + (self.scale*p.v[2] + self.turbulent_noise(p, visible_octaves(width, 7))*10.)).sin();
+        // Veins thinner than the pixel fade to their average, which is 0
+        let fade = ((self.scale * width / std::f64::consts::PI - 0.5) / 1.5).clamp(0., 1.);
+        colorRGB::from(1.,1.,1.) * (marble * (1. - fade))
+    }
+}
+
+/// Grey turbulence of a Perlin noise, for bump maps and masks
+pub struct Turbulence_Tex {
+    noise: Perlin_Noise,
+    scale: f64,
+    depth: i32,
+}
+
+impl Turbulence_Tex {
+    pub fn from(scale: f64, depth: i32) -> Turbulence_Tex {
+        Turbulence_Tex { noise: Perlin_Noise::new(), scale, depth }
+    }
+}
+
+impl Texture for Turbulence_Tex {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::from(1.,1.,1.) * self.noise.turbulent_noise(&(*p * self.scale), self.depth)
+    }
+
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        let depth = visible_octaves(footprint.world_width() * self.scale, self.depth);
+        colorRGB::from(1.,1.,1.) * self.noise.turbulent_noise(&(*p * self.scale), depth)
+    }
 }
 
 /// Linear Interpolations
@@ -339,53 +491,344 @@ pub fn vec3_trilerp(v: [vec3; 2*2*2], t: [f64; 3]) -> f64 {
 
 use std::fs;
 use stb_image::image;
+use crate::rtow_math::vec2::*;
+use std::sync::Arc;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TexFilter {
+    Nearest,
+    Bilinear,
+    // Catmull-Rom, sharper than bilinear when magnified
+    Bicubic,
+}
+
+/// Minification with a footprint: mip level from the pixel size, anisotropic takes up to n
+/// trilinear samples along the long side of stretched footprints (grazing angles)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TexMip {
+    Off,
+    Trilinear,
+    Anisotropic(usize),
+}
+
+/// What happens to uv out of [0,1]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TexWrap {
+    Repeat,
+    Mirror,
+    Clamp,
+}
 
+/// sRGB transfer curve to linear, colour images are stored with it
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+fn wrap_index(i: i64, size: usize, wrap: TexWrap) -> usize {
+    let n = size as i64;
+    match wrap {
+        TexWrap::Repeat => i.rem_euclid(n) as usize,
+        TexWrap::Clamp => i.clamp(0, n - 1) as usize,
+        TexWrap::Mirror => {
+            let m = i.rem_euclid(2 * n);
+            (if m >= n { 2 * n - 1 - m } else { m }) as usize
+        }
+    }
+}
+
+/// Image with 1 (grey), 2 (grey, alpha), 3 (rgb) or 4 (rgba) channels, stored as linear floats
+/// Reads whatever stb_image does (8 bit, .hdr as float) and netpbm .ppm/.pgm, those up to 16 bit.
+/// load() is for colours, 8 and 16 bit images get decoded from sRGB. Normal, bump or roughness
+/// maps hold data instead and go through load_linear(). Float images are always linear.
+/// Filtering, wrap and the uv transform can be changed with ..RTOW_Image::load(&path)
+/// By default u repeats and v is clamped: latitude-longitude maps (earthmap.jpg) go around in u
+/// but their poles are not next to each other
 pub struct RTOW_Image {
-    image: image::LoadResult,
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub data: Vec<f32>,
+    pub filter: TexFilter,
+    pub wrap_u: TexWrap,
+    pub wrap_v: TexWrap,
+    pub mip: TexMip,
+    // The lookup uses uv * uv_scale + uv_offset
+    pub uv_scale: vec2,
+    pub uv_offset: vec2,
+    // Each half the size of the previous one, the image itself is level 0
+    pub mips: Vec<mip_level>,
+}
+
+pub struct mip_level {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+impl mip_level {
+    /// Box filtered to half the size, odd sizes round up and repeat their last row/column
+    fn half(width: usize, height: usize, channels: usize, data: &[f32]) -> mip_level {
+        let (w, h) = ((width + 1) / 2, (height + 1) / 2);
+        let mut half = vec![0.; w * h * channels];
+        for j in 0..h {
+            for i in 0..w {
+                for (di, dj) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let src = ((2 * i + di).min(width - 1) + (2 * j + dj).min(height - 1) * width) * channels;
+                    for c in 0..channels { half[(i + j * w) * channels + c] += 0.25 * data[src + c] }
+                }
+            }
+        }
+        mip_level { width: w, height: h, data: half }
+    }
 }
 
 impl RTOW_Image {
     pub fn load(path: &String) -> RTOW_Image {
-        match image::load(path) {
-            image::LoadResult::Error(e) => panic!("Failed to load: {}", e),
-            (img) => {
-                RTOW_Image{image:img}
+        RTOW_Image::load_decoded(path, true)
+    }
+
+    pub fn load_linear(path: &String) -> RTOW_Image {
+        RTOW_Image::load_decoded(path, false)
+    }
+
+    /// Rows from top to bottom, channels interleaved, values already linear
+    pub fn from_data(width: usize, height: usize, channels: usize, data: Vec<f32>) -> RTOW_Image {
+        if width == 0 || height == 0 || !(1..=4).contains(&channels) || data.len() < width * height * channels {
+            panic!("Bad image: {}x{} with {} channels and {} values", width, height, channels, data.len());
+        }
+        let mut mips: Vec<mip_level> = Vec::new();
+        let (mut w, mut h) = (width, height);
+        while w > 1 || h > 1 {
+            let level = match mips.last() {
+                Some(prev) => mip_level::half(w, h, channels, &prev.data),
+                None => mip_level::half(w, h, channels, &data),
+            };
+            (w, h) = (level.width, level.height);
+            mips.push(level);
+        }
+
+        RTOW_Image {
+            width, height, channels, data,
+            filter: TexFilter::Bilinear,
+            wrap_u: TexWrap::Repeat,
+            wrap_v: TexWrap::Clamp,
+            mip: TexMip::Trilinear,
+            uv_scale: vec2::from(1., 1.),
+            uv_offset: vec2::new(),
+            mips,
+        }
+    }
+
+    fn load_decoded(path: &String, srgb: bool) -> RTOW_Image {
+        let lower = path.to_lowercase();
+        let (width, height, channels, mut data, is_float) =
+            if lower.ends_with(".ppm") || lower.ends_with(".pgm") || lower.ends_with(".pnm") {
+                let (w, h, c, d) = RTOW_Image::load_pnm(path);
+                (w, h, c, d, false)
+            } else {
+                match image::load(path) {
+                    image::LoadResult::Error(e) => panic!("Failed to load: {}", e),
+                    image::LoadResult::ImageU8(img) => (img.width, img.height, img.depth, img.data.iter().map(|x| *x as f32 / 255.).collect(), false),
+                    image::LoadResult::ImageF32(img) => (img.width, img.height, img.depth, img.data, true),
+                }
+            };
+
+        if srgb && !is_float {
+            // Alpha is always linear
+            let colour_channels = if channels % 2 == 0 { channels - 1 } else { channels };
+            for (i, x) in data.iter_mut().enumerate() {
+                if i % channels < colour_channels { *x = srgb_to_linear(*x as f64) as f32 }
+            }
+        }
+        RTOW_Image::from_data(width, height, channels, data)
+    }
+
+    /// Netpbm grey (P2, P5) and rgb (P3, P6), values over 255 in the header mean 16 bit
+    fn load_pnm(path: &String) -> (usize, usize, usize, Vec<f32>) {
+        let bytes = fs::read(path).unwrap_or_else(|e| panic!("Failed to load: {}", e));
+
+        // Header: magic, width, height and max value, between whitespace and # comments
+        let mut pos = 0;
+        let mut header: Vec<String> = Vec::new();
+        while header.len() < 4 {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() { pos += 1 }
+            if pos < bytes.len() && bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' { pos += 1 }
+                continue;
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() { pos += 1 }
+            if start == pos { panic!("Failed to load: {} has a truncated header", path) }
+            header.push(String::from_utf8_lossy(&bytes[start..pos]).to_string());
+        }
+        // A single whitespace separates the header from binary data
+        pos += 1;
+
+        let number = |s: &String| s.parse::<usize>().unwrap_or_else(|_| panic!("Failed to load: {} has a bad header", path));
+        let (width, height, max_val) = (number(&header[1]), number(&header[2]), number(&header[3]));
+        let channels = match header[0].as_str() {
+            "P2" | "P5" => 1,
+            "P3" | "P6" => 3,
+            _ => panic!("Failed to load: {} is not a P2, P3, P5 or P6 image", path),
+        };
+
+        let count = width * height * channels;
+        let scale = 1. / max_val as f32;
+        let body = if pos < bytes.len() { &bytes[pos..] } else { &[] };
+        let data: Vec<f32> = match header[0].as_str() {
+            "P5" | "P6" if max_val > 255 => body.chunks_exact(2).take(count).map(|b| u16::from_be_bytes([b[0], b[1]]) as f32 * scale).collect(),
+            "P5" | "P6" => body.iter().take(count).map(|b| *b as f32 * scale).collect(),
+            _ => String::from_utf8_lossy(body).split_whitespace().take(count).map(|s| s.parse::<f32>().unwrap_or(0.) * scale).collect(),
+        };
+        if data.len() < count { panic!("Failed to load: {} is truncated", path) }
+
+        (width, height, channels, data)
+    }
+
+    fn level_size(&self, level: usize) -> (usize, usize) {
+        if level == 0 { (self.width, self.height) } else { (self.mips[level - 1].width, self.mips[level - 1].height) }
+    }
+
+    /// Texel (i, j) of a mip level after wrapping, j = 0 is the top row
+    fn texel(&self, level: usize, i: i64, j: i64) -> [f64; 4] {
+        let (w, h) = self.level_size(level);
+        let data = if level == 0 { &self.data } else { &self.mips[level - 1].data };
+        let idx = (wrap_index(i, w, self.wrap_u) + wrap_index(j, h, self.wrap_v) * w) * self.channels;
+        let mut ret = [0.; 4];
+        for c in 0..self.channels { ret[c] = data[idx + c] as f64 }
+        ret
+    }
+
+    /// Sum of the texels from (i0, j0) on, weighted by wx along x and wy along y
+    fn weighted(&self, level: usize, i0: i64, j0: i64, wx: &[f64], wy: &[f64]) -> [f64; 4] {
+        let mut ret = [0.; 4];
+        for (b, w_y) in wy.iter().enumerate() {
+            for (a, w_x) in wx.iter().enumerate() {
+                let t = self.texel(level, i0 + a as i64, j0 + b as i64);
+                for c in 0..4 { ret[c] += t[c] * w_x * w_y }
             }
         }
+        ret
+    }
+
+    /// Filtered channels at uv, in the order of the image
+    pub fn sample(&self, u: f64, v: f64) -> [f64; 4] {
+        self.sample_level(0, u, v)
+    }
+
+    fn sample_level(&self, level: usize, u: f64, v: f64) -> [f64; 4] {
+        let (w, h) = self.level_size(level);
+        let s = u * self.uv_scale.v[0] + self.uv_offset.v[0];
+        let t = v * self.uv_scale.v[1] + self.uv_offset.v[1];
+        // Texel centers are at half pixels, v is flipped because images are stored from the top
+        let x = s * w as f64 - 0.5;
+        let y = (1. - t) * h as f64 - 0.5;
+        if !x.is_finite() || !y.is_finite() { return [0.; 4] }
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (i, j) = (x0 as i64, y0 as i64);
+        match self.filter {
+            TexFilter::Nearest => self.texel(level, x.round() as i64, y.round() as i64),
+            TexFilter::Bilinear => self.weighted(level, i, j, &[1. - fx, fx], &[1. - fy, fy]),
+            TexFilter::Bicubic => {
+                let mut ret = self.weighted(level, i - 1, j - 1, &catmull_rom(fx), &catmull_rom(fy));
+                // The negative lobes can undershoot at hard edges
+                for c in ret.iter_mut() { *c = c.max(0.) }
+                ret
+            }
+        }
+    }
+
+    /// Blend of the two mip levels around a fractional one
+    fn sample_trilinear(&self, level: f64, u: f64, v: f64) -> [f64; 4] {
+        let level = level.clamp(0., self.mips.len() as f64);
+        let l0 = level.floor() as usize;
+        let t = level - l0 as f64;
+        let a = self.sample_level(l0, u, v);
+        if t <= 0. || l0 == self.mips.len() { return a }
+        let b = self.sample_level(l0 + 1, u, v);
+        let mut ret = [0.; 4];
+        for c in 0..4 { ret[c] = a[c] * (1. - t) + b[c] * t }
+        ret
+    }
+
+    /// Channels averaged over the footprint of a pixel
+    pub fn sample_footprint(&self, u: f64, v: f64, footprint: &tex_footprint) -> [f64; 4] {
+        if self.mip == TexMip::Off || footprint.is_point() { return self.sample(u, v) }
+
+        // Footprint axes in texels of the full image
+        let to_texels = |du: f64, dv: f64| vec2::from(du * self.uv_scale.v[0] * self.width as f64, dv * self.uv_scale.v[1] * self.height as f64);
+        let (dx, dy) = (to_texels(footprint.dudx, footprint.dvdx), to_texels(footprint.dudy, footprint.dvdy));
+        let (len_x, len_y) = (dx.length(), dy.length());
+        let (major, minor) = (len_x.max(len_y), len_x.min(len_y));
+
+        match self.mip {
+            TexMip::Anisotropic(max_samples) if max_samples > 1 && major > minor * 1.5 => {
+                // Level for the short side, samples spread along the long one
+                let minor = minor.max(major / max_samples as f64);
+                let n = ((major / minor).ceil() as usize).clamp(1, max_samples);
+                let (du, dv) = if len_x > len_y { (footprint.dudx, footprint.dvdx) } else { (footprint.dudy, footprint.dvdy) };
+                let level = minor.max(1e-8).log2();
+                let mut ret = [0.; 4];
+                for k in 0..n {
+                    let s = (k as f64 + 0.5) / n as f64 - 0.5;
+                    let t = self.sample_trilinear(level, u + du * s, v + dv * s);
+                    for c in 0..4 { ret[c] += t[c] / n as f64 }
+                }
+                ret
+            },
+            _ => self.sample_trilinear(major.max(1e-8).log2(), u, v),
+        }
+    }
+
+    /// Opacity at uv, 1 for images without alpha
+    pub fn alpha(&self, u: f64, v: f64) -> f64 {
+        match self.channels {
+            2 => self.sample(u, v)[1],
+            4 => self.sample(u, v)[3],
+            _ => 1.,
+        }
+    }
+}
+
+/// Weights of the 4 texels around a point at t in [0,1] between the middle two
+fn catmull_rom(t: f64) -> [f64; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        0.5 * (-t3 + 2. * t2 - t),
+        0.5 * (3. * t3 - 5. * t2 + 2.),
+        0.5 * (-3. * t3 + 4. * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
+
+impl RTOW_Image {
+    fn to_color(&self, t: [f64; 4]) -> colorRGB {
+        match self.channels {
+            1 | 2 => colorRGB::from(t[0], t[0], t[0]),
+            _ => colorRGB::from(t[0], t[1], t[2]),
+        }
     }
 }
 
 impl Texture for RTOW_Image {
     fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
-        let mut ret = colorRGB::from(0.,1.,1.);
-        match &self.image {
-            image::LoadResult::ImageF32(img) => {
-                panic!("Not Supported for now");
-            },
-            image::LoadResult::ImageU8(img) => {
-                let clamped_u = u.clamp(0.,1.);
-                let clamped_v = 1. - v.clamp(0.,1.);
-                // Flipped V because we render from bottom to top
-
-                let f64_w = img.width as f64;
-                let f64_h = img.height as f64;
-                let t_i = (clamped_u * f64_w);
-                let t_j = (clamped_v * f64_h);
-
-                let i = if t_i >= f64_w {(f64_w - 1.) as i32} else {t_i as i32};
-                let j = if t_j >= f64_h {(f64_h - 1.) as i32} else {t_j as i32};
-
-                let color_scale = 1./255.;
-                let v1 = (i*3 + j*3*img.width as i32) as usize;
-                colorRGB::from(
-                    img.data[v1] as f64 * color_scale, 
-                    img.data[v1+1] as f64 * color_scale, 
-                    img.data[v1+2] as f64 * color_scale, 
-                )
+        self.to_color(self.sample(u, v))
+    }
 
-            },
-            (_) => panic!("An error passed through the load!"),
-        }
+    fn value_filtered(&self, u: f64, v: f64, p: &point3, footprint: &tex_footprint) -> colorRGB {
+        self.to_color(self.sample_footprint(u, v, footprint))
+    }
+}
 
+/// Alpha channel of an image as a grey texture, for cutout masks
+pub struct Image_Alpha {
+    pub image: Arc<RTOW_Image>,
+}
+
+impl Texture for Image_Alpha {
+    fn value(&self, u: f64, v: f64, p: &point3) -> colorRGB {
+        colorRGB::one() * self.image.alpha(u, v)
     }
-}
\ No newline at end of file
+}
@@ -36,6 +36,8 @@ impl aabb {
                 alpha: 0.1, 
                 index_refr: 0.,
                 tex: Arc::new(Solid_Color::new()),
+                absorption: colorRGB::new(),
+                dispersion: Dispersion::Constant,
             })
         }
     }
@@ -44,6 +46,8 @@ impl aabb {
         alpha: 0.1, 
         index_refr: 0.,
         tex: Arc::new(Solid_Color::new()),
+        absorption: colorRGB::new(),
+        dispersion: Dispersion::Constant,
     })} }
 
     pub fn from_2_aabb(b1: aabb, b2: aabb) -> aabb {
@@ -105,6 +109,22 @@ impl aabb {
         true
     }
 
+    /// Same as hit_fast but also returns where the ray enters and leaves the box
+    pub fn hit_range(&self, r: &ray, t_min: f64, t_max: f64) -> (bool, f64, f64) {
+        let (mut calc_min, mut calc_max) = (t_min, t_max);
+        for i in 0..3 {
+            let inverse_dir = 1. / r.dir.v[i];
+            let mut t0 = (self.min.v[i] - r.origin.v[i]) * inverse_dir;
+            let mut t1 = (self.max.v[i] - r.origin.v[i]) * inverse_dir;
+            if inverse_dir < 0. { std::mem::swap(&mut t0, &mut t1) };
+
+            calc_min = t0.max(calc_min);
+            calc_max = t1.min(calc_max);
+            if calc_max <= calc_min { return (false, calc_min, calc_max) }
+        }
+        (true, calc_min, calc_max)
+    }
+
     pub fn hit_branchless(&self, r: &ray, t_min: f64, t_max: f64) -> bool {
         let (mut calc_min, mut calc_max) = (t_min, t_max);
         let inverse_dir = vec3::from(1./r.dir.v[0], 1./r.dir.v[1], 1./r.dir.v[2]);
@@ -0,0 +1,81 @@
+use crate::materials::prelude::*;
+use crate::rtow_math::prelude::*;
+use crate::objects::prelude::*;
+use std::sync::Arc;
+
+/// Opacity mask over any object (leaves, fences, decals), the first channel of mask is the opacity.
+/// Hits where the object is see through are skipped and the ray looks for the next one,
+/// so bvh_node::hit, the closest hit and shadow rays of light sampling just go through the holes.
+pub struct cutout {
+    obj: Box<dyn Hittable>,
+    mask: Arc<dyn Texture>,
+    // Opaque from this opacity up, None passes through randomly with probability 1 - opacity
+    threshold: Option<f64>,
+}
+
+// Enough for a few layers of the same object (heightfields, quadrics...)
+const MAX_SKIPS: i32 = 16;
+
+impl cutout {
+    pub fn from(obj: Box<dyn Hittable>, mask: Arc<dyn Texture>, threshold: f64) -> cutout {
+        cutout { obj, mask, threshold: Some(threshold) }
+    }
+
+    /// Partial opacity for soft edges and see through surfaces, noisy but right on average
+    pub fn stochastic(obj: Box<dyn Hittable>, mask: Arc<dyn Texture>) -> cutout {
+        cutout { obj, mask, threshold: None }
+    }
+
+    fn opaque(&self, rec: &hit_record) -> bool {
+        let opacity = self.mask.value(rec.uv.v[0], rec.uv.v[1], &rec.p).v[0];
+        match self.threshold {
+            Some(threshold) => opacity >= threshold,
+            None => rand_f64() < opacity,
+        }
+    }
+}
+
+unsafe impl Sync for cutout{}
+unsafe impl Send for cutout{}
+
+impl Hittable for cutout {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        // rec may already hold a closer hit of another object, only touch it when something is found
+        let mut temp_rec = hit_record::new();
+        let mut t_start = t_min;
+        for _ in 0..MAX_SKIPS {
+            if !self.obj.hit(r, t_start, t_max, &mut temp_rec) { return false }
+            if self.opaque(&temp_rec) {
+                *rec = temp_rec;
+                return true
+            }
+            t_start = temp_rec.t + 0.0001;
+        }
+        false
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        self.obj.get_aabb(time0, time1)
+    }
+
+    // As a light the holes are ignored, samples that land on one are lost
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 {
+        self.obj.pdf_value(origin, dir)
+    }
+
+    fn random(&self, origin: &point3) -> vec3 {
+        self.obj.random(origin)
+    }
+
+    fn light_bounds(&self) -> Option<light_bounds> {
+        self.obj.light_bounds()
+    }
+
+    fn sample_surface(&self) -> Option<(hit_record, f64)> {
+        self.obj.sample_surface()
+    }
+
+    fn surface_pdf(&self, p: &point3) -> f64 {
+        self.obj.surface_pdf(p)
+    }
+}
@@ -0,0 +1,107 @@
+use crate::materials::prelude::*;
+use crate::rtow_math::prelude::*;
+use crate::objects::prelude::*;
+
+/// What rays that miss everything see
+pub trait Background: Send + Sync {
+    /// Radiance coming from direction dir (not normalized)
+    fn value(&self, dir: &vec3) -> colorRGB;
+}
+
+/// Plain colour in every direction, the old bg_col
+impl Background for colorRGB {
+    fn value(&self, dir: &vec3) -> colorRGB {
+        *self
+    }
+}
+
+/// Light from an equirectangular image (.hdr) around the whole scene, image based lighting
+/// Directions map to the image like sphere_uv: v = 1 (the top row) is straight up.
+/// Used as the background it is what misses see, added to the lights it is also sampled
+/// directly, proportionally to the luminance of the image so small bright parts (the sun, windows)
+/// are found without noise.
+pub struct environment_light {
+    image: RTOW_Image,
+    distribution: distribution_2d,
+    pub intensity: f64,
+    // Degrees around the y axis
+    pub rotation: f64,
+}
+
+impl environment_light {
+    pub fn load(path: &String, intensity: f64, rotation: f64) -> environment_light {
+        environment_light::from_image(RTOW_Image::load(path), intensity, rotation)
+    }
+
+    pub fn from_image(image: RTOW_Image, intensity: f64, rotation: f64) -> environment_light {
+        // Rows near the poles cover less solid angle, sin(theta) keeps them from being oversampled
+        let (w, h) = (image.width, image.height);
+        let mut values = vec![0.; w * h];
+        for j in 0..h {
+            let sin_theta = (pi * (j as f64 + 0.5) / h as f64).sin();
+            for i in 0..w {
+                let idx = (i + j * w) * image.channels;
+                let lum = if image.channels >= 3 {
+                    0.2126 * image.data[idx] as f64 + 0.7152 * image.data[idx + 1] as f64 + 0.0722 * image.data[idx + 2] as f64
+                } else { image.data[idx] as f64 };
+                values[i + j * w] = lum * sin_theta;
+            }
+        }
+        // The image is a panorama, wrap the lookups around in u. Texels are looked up as they are, the
+        // distribution is constant over each one: bilinear would spread the sun into dim texels
+        // that are rarely sampled, and those rays come back as fireflies
+        let image = RTOW_Image { wrap_u: TexWrap::Repeat, wrap_v: TexWrap::Clamp, mip: TexMip::Off, filter: TexFilter::Nearest, ..image };
+        environment_light { distribution: distribution_2d::from(&values, w, h), image, intensity, rotation }
+    }
+
+    fn rotate(&self, dir: &vec3, angle: f64) -> vec3 {
+        let (s, c) = deg_to_rad(angle).sin_cos();
+        vec3::from(c * dir.v[0] + s * dir.v[2], dir.v[1], -s * dir.v[0] + c * dir.v[2])
+    }
+
+    /// uv of a world direction
+    fn dir_uv(&self, dir: &vec3) -> point2 {
+        sphere_uv(&self.rotate(&dir.unit_vec(), -self.rotation))
+    }
+
+    /// World direction of a uv, inverse of dir_uv
+    fn uv_dir(&self, u: f64, v: f64) -> vec3 {
+        let (theta, phi) = (v * pi, u * 2. * pi);
+        let local = vec3::from(-theta.sin() * phi.cos(), -theta.cos(), theta.sin() * phi.sin());
+        self.rotate(&local, self.rotation)
+    }
+}
+
+impl Background for environment_light {
+    fn value(&self, dir: &vec3) -> colorRGB {
+        let uv = self.dir_uv(dir);
+        let c = self.image.sample(uv.v[0], uv.v[1]);
+        let rgb = if self.image.channels >= 3 { colorRGB::from(c[0], c[1], c[2]) } else { colorRGB::one() * c[0] };
+        rgb * self.intensity
+    }
+}
+
+// Infinitely far, it can't be hit and has no box. It is only a Hittable to go in the lights list
+impl Hittable for environment_light {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        false
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        (false, aabb::new())
+    }
+
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 {
+        let uv = self.dir_uv(dir);
+        let sin_theta = (uv.v[1] * pi).sin();
+        if sin_theta <= 0. { return 0. }
+        // The distribution rows go from the top of the image, v from the bottom.
+        // The uv square maps to the sphere with dw = 2 pi^2 sin(theta) du dv
+        self.distribution.pdf(uv.v[0], 1. - uv.v[1]) / (2. * pi * pi * sin_theta)
+    }
+
+    fn random(&self, origin: &point3) -> vec3 {
+        let (x, y, _) = self.distribution.sample_continuous(rand_f64(), rand_f64());
+        self.uv_dir(x, 1. - y)
+    }
+}
@@ -0,0 +1,182 @@
+use crate::materials::prelude::*;
+use std::sync::Arc;
+use crate::rtow_math::prelude::*;
+use crate::objects::prelude::*;
+
+/// Terrain from a grid of nx * nz heights spread over size_x * size_z starting at origin
+/// Every cell of the grid is 2 triangles, the ray walks the cells it crosses (grid DDA)
+/// and only tests the triangles of cells whose height range it passes through.
+/// Only the heights are stored, cell bounds and normals are computed when needed.
+pub struct heightfield {
+    origin: point3,
+    size_x: f64,
+    size_z: f64,
+    nx: usize,
+    nz: usize,
+    heights: Vec<f64>,
+    bbox: aabb,
+    mat: Arc<dyn Material>,
+}
+
+impl heightfield {
+    /// heights go row by row along x: heights[i + j * nx] is at x = i, z = j
+    pub fn from_grid(heights: Vec<f64>, nx: usize, nz: usize, origin: point3, size_x: f64, size_z: f64, mat: Arc<dyn Material>) -> heightfield {
+        if nx < 2 || nz < 2 || heights.len() != nx * nz { panic!("Heightfield needs at least 2x2 heights and nx * nz values") };
+
+        let (min_h, max_h) = heights.iter().fold((INFINITY, -INFINITY), |(mn, mx), h| (mn.min(*h), mx.max(*h)));
+        let bbox = aabb::from(
+            point3::from(origin.v[0], origin.v[1] + min_h - 0.0001, origin.v[2]),
+            point3::from(origin.v[0] + size_x, origin.v[1] + max_h + 0.0001, origin.v[2] + size_z));
+
+        heightfield { origin, size_x, size_z, nx, nz, heights, bbox, mat }
+    }
+
+    /// Grayscale of the image (first channel) as height in [0, height_scale]
+    /// 16 bit .pgm heightmaps avoid the terracing of 8 bit ones
+    pub fn from_image(path: &String, origin: point3, size_x: f64, size_z: f64, height_scale: f64, mat: Arc<dyn Material>) -> heightfield {
+        let img = RTOW_Image::load_linear(path);
+        let heights = (0..img.width * img.height).map(|i| img.data[i * img.channels] as f64 * height_scale).collect();
+        heightfield::from_grid(heights, img.width, img.height, origin, size_x, size_z, mat)
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (self.size_x / (self.nx - 1) as f64, self.size_z / (self.nz - 1) as f64)
+    }
+
+    fn height(&self, i: usize, j: usize) -> f64 {
+        self.heights[i + j * self.nx]
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> point3 {
+        let (dx, dz) = self.cell_size();
+        self.origin + vec3::from(i as f64 * dx, self.height(i, j), j as f64 * dz)
+    }
+
+    /// Central differences of the neighbour heights
+    fn vertex_normal(&self, i: usize, j: usize) -> vec3 {
+        let (dx, dz) = self.cell_size();
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.nx - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.nz - 1));
+        let slope_x = (self.height(i1, j) - self.height(i0, j)) / ((i1 - i0) as f64 * dx);
+        let slope_z = (self.height(i, j1) - self.height(i, j0)) / ((j1 - j0) as f64 * dz);
+        vec3::from(-slope_x, 1., -slope_z).unit_vec()
+    }
+
+    /// Moller-Trumbore, returns t and the barycentric weights of b and c
+    fn hit_triangle(r: &ray, a: &point3, b: &point3, c: &point3) -> (bool, f64, f64, f64) {
+        let e1 = *b - *a;
+        let e2 = *c - *a;
+        let pv = r.dir.cross(&e2);
+        let det = e1.dot(&pv);
+        if det.abs() < 1e-12 { return (false, 0., 0., 0.) }
+
+        let inv = 1. / det;
+        let tv = r.origin - *a;
+        let u = tv.dot(&pv) * inv;
+        if u < 0. || u > 1. { return (false, 0., 0., 0.) }
+
+        let qv = tv.cross(&e1);
+        let v = r.dir.dot(&qv) * inv;
+        if v < 0. || u + v > 1. { return (false, 0., 0., 0.) }
+
+        (true, e2.dot(&qv) * inv, u, v)
+    }
+
+    /// Both triangles of cell (i, j), fills rec with the closest hit in [t_min, t_max]
+    fn hit_cell(&self, r: &ray, i: usize, j: usize, t_min: f64, t_max: f64, rec: &mut hit_record) -> bool {
+        let idx = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let verts = idx.map(|(a, b)| self.vertex(a, b));
+
+        let mut closest = t_max;
+        let mut best: Option<([usize; 3], f64, f64)> = None;
+        for tri in [[0, 1, 2], [0, 2, 3]] {
+            let (check, t, u, v) = heightfield::hit_triangle(r, &verts[tri[0]], &verts[tri[1]], &verts[tri[2]]);
+            if check && t >= t_min && t <= closest {
+                closest = t;
+                best = Some((tri, u, v));
+            }
+        }
+
+        let (tri, u, v) = match best {
+            Some(b) => b,
+            None => return false,
+        };
+
+        let n = self.vertex_normal(idx[tri[0]].0, idx[tri[0]].1) * (1. - u - v)
+            + self.vertex_normal(idx[tri[1]].0, idx[tri[1]].1) * u
+            + self.vertex_normal(idx[tri[2]].0, idx[tri[2]].1) * v;
+
+        rec.t = closest;
+        rec.p = r.at(closest);
+        let n = n.unit_vec();
+        rec.set_face_normal(r, n);
+        // Tangents follow the planar uv up the slope given by the interpolated normal
+        rec.set_tangents(
+            vec3::from(1., -n.v[0] / n.v[1], 0.) * self.size_x,
+            vec3::from(0., -n.v[2] / n.v[1], 1.) * self.size_z);
+        rec.mat = Arc::clone(&self.mat);
+        rec.uv.v[0] = ((rec.p.v[0] - self.origin.v[0]) / self.size_x).clamp(0., 1.);
+        rec.uv.v[1] = ((rec.p.v[2] - self.origin.v[2]) / self.size_z).clamp(0., 1.);
+        true
+    }
+}
+
+unsafe impl Sync for heightfield{}
+unsafe impl Send for heightfield{}
+
+impl Hittable for heightfield {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        let (check, t_enter, t_exit) = self.bbox.hit_range(r, t_min, t_max);
+        if !check { return false }
+
+        let (dx, dz) = self.cell_size();
+        let (last_i, last_j) = ((self.nx - 2) as i64, (self.nz - 2) as i64);
+
+        // Starting cell
+        let start = r.at(t_enter);
+        let mut i = (((start.v[0] - self.origin.v[0]) / dx).floor() as i64).clamp(0, last_i);
+        let mut j = (((start.v[2] - self.origin.v[2]) / dz).floor() as i64).clamp(0, last_j);
+
+        // t to cross a whole cell, and t of the next cell border, per axis
+        let step_i: i64 = if r.dir.v[0] >= 0. { 1 } else { -1 };
+        let step_j: i64 = if r.dir.v[2] >= 0. { 1 } else { -1 };
+        let delta_i = if r.dir.v[0] != 0. { dx / r.dir.v[0].abs() } else { INFINITY };
+        let delta_j = if r.dir.v[2] != 0. { dz / r.dir.v[2].abs() } else { INFINITY };
+        let border_x = self.origin.v[0] + (i + if step_i > 0 { 1 } else { 0 }) as f64 * dx;
+        let border_z = self.origin.v[2] + (j + if step_j > 0 { 1 } else { 0 }) as f64 * dz;
+        let mut next_i = if r.dir.v[0] != 0. { (border_x - r.origin.v[0]) / r.dir.v[0] } else { INFINITY };
+        let mut next_j = if r.dir.v[2] != 0. { (border_z - r.origin.v[2]) / r.dir.v[2] } else { INFINITY };
+
+        let mut t_cell = t_enter;
+        while t_cell <= t_exit {
+            let t_out = next_i.min(next_j).min(t_exit);
+
+            // Skip the cell if the ray is above or below all of its corners meanwhile
+            let (ui, uj) = (i as usize, j as usize);
+            let corners = [self.height(ui, uj), self.height(ui + 1, uj), self.height(ui, uj + 1), self.height(ui + 1, uj + 1)];
+            let cell_min = self.origin.v[1] + corners.iter().cloned().fold(INFINITY, f64::min);
+            let cell_max = self.origin.v[1] + corners.iter().cloned().fold(-INFINITY, f64::max);
+            let (y0, y1) = (r.at(t_cell).v[1], r.at(t_out).v[1]);
+            if y0.max(y1) >= cell_min && y0.min(y1) <= cell_max {
+                if self.hit_cell(r, ui, uj, t_min, t_max, rec) { return true }
+            }
+
+            if next_i < next_j {
+                i += step_i;
+                t_cell = next_i;
+                next_i += delta_i;
+            } else {
+                j += step_j;
+                t_cell = next_j;
+                next_j += delta_j;
+            }
+            if i < 0 || i > last_i || j < 0 || j > last_j { break }
+        }
+
+        false
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        (true, self.bbox.clone())
+    }
+}
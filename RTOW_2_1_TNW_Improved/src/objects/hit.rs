@@ -4,6 +4,9 @@ use crate::objects::sphere::*;
 use crate::materials::*;
 use crate::objects::aabb::*;
 use crate::rtow_math::vec2::*;
+use crate::rtow_math::onb::*;
+use crate::materials::textures::*;
+use crate::objects::light_sampler::*;
 
 use std::sync::Arc;
 
@@ -16,6 +19,11 @@ pub struct hit_record {
     pub mat: Arc<dyn Material>,
     pub iters: i32,
     pub uv: point2,
+    // dp/du and dp/dv of the surface, not normalized, zero if the object has no uv frame
+    pub tangent: vec3,
+    pub bitangent: vec3,
+    // Set by integrators whose rays have differentials, see set_footprint()
+    pub footprint: tex_footprint,
 }
 
 impl hit_record {
@@ -29,12 +37,98 @@ impl hit_record {
                 mat: Arc::new(new_def),
                 iters: 0,
                 uv: point2::new(),
+                tangent: vec3::new(),
+                bitangent: vec3::new(),
+                footprint: tex_footprint::new(),
             }
         }
     
+    /// Also clears the tangents, objects that have them set them after this
     pub fn set_face_normal(&mut self, r: &ray, out_n: vec3) {
         self.front_face = r.dir.dot(&out_n) < 0.;
         self.n = if self.front_face {out_n} else {out_n * -1.};
+        self.tangent = vec3::new();
+        self.bitangent = vec3::new();
+    }
+
+    pub fn set_tangents(&mut self, dpdu: vec3, dpdv: vec3) {
+        self.tangent = dpdu;
+        self.bitangent = dpdv;
+    }
+
+    /// Texture lookup at the hit, filtered over the footprint when there is one
+    pub fn texture(&self, tex: &Arc<dyn Texture>) -> colorRGB {
+        tex.value_hit(self)
+    }
+
+    /// Where the differentials of r cross the tangent plane of the hit, and the uv change that
+    /// means through the tangents (least squares, the tangents don't need to be orthogonal)
+    pub fn set_footprint(&mut self, r: &ray) {
+        let diff = match r.differential {
+            Some(diff) => diff,
+            None => { self.footprint = tex_footprint::new(); return }
+        };
+
+        let plane_d = self.n.dot(&self.p);
+        let cross_plane = |origin: &point3, dir: &vec3| -> Option<vec3> {
+            let denom = self.n.dot(dir);
+            if denom.abs() < 1e-12 { return None }
+            let t = (plane_d - self.n.dot(origin)) / denom;
+            Some(*origin + *dir * t - self.p)
+        };
+        let (dpdx, dpdy) = match (cross_plane(&diff.rx_origin, &diff.rx_dir), cross_plane(&diff.ry_origin, &diff.ry_dir)) {
+            (Some(dpdx), Some(dpdy)) => (dpdx, dpdy),
+            _ => { self.footprint = tex_footprint::new(); return }
+        };
+
+        let (a, b, c) = (self.tangent.dot(&self.tangent), self.tangent.dot(&self.bitangent), self.bitangent.dot(&self.bitangent));
+        let det = a * c - b * b;
+        let to_uv = |d: &vec3| -> (f64, f64) {
+            if det.abs() < 1e-12 { return (0., 0.) }
+            let (e, f) = (self.tangent.dot(d), self.bitangent.dot(d));
+            ((c * e - b * f) / det, (a * f - b * e) / det)
+        };
+        let (dudx, dvdx) = to_uv(&dpdx);
+        let (dudy, dvdy) = to_uv(&dpdy);
+        self.footprint = tex_footprint { dpdx, dpdy, dudx, dvdx, dudy, dvdy };
+    }
+
+    /// Differentials for the ray scattered at this hit. Specular bounces bend the neighbour rays
+    /// like the main one, as if the surface were flat around the hit. Anything else spreads
+    /// light too much for a footprint to mean anything and drops them (scattering_pdf > 0)
+    pub fn bounce_differential(&self, r: &ray, scattered: &ray, scattering_pdf: f64) -> Option<ray_differential> {
+        let diff = r.differential?;
+        if scattering_pdf > 0. || self.footprint.is_point() { return None }
+
+        let d_in = r.dir.unit_vec();
+        let d_out = scattered.dir.unit_vec();
+        let n = self.n;
+        let reflected = d_out.dot(&n) > 0.;
+        // Ratio of iors from Snell's law on the main ray, 1 when it goes straight through
+        let (cos_i, cos_t) = (d_in.dot(&n).abs().min(1.), d_out.dot(&n).abs().min(1.));
+        let sin_i = (1. - cos_i * cos_i).sqrt();
+        let eta = if sin_i > 1e-4 { (1. - cos_t * cos_t).sqrt() / sin_i } else { 1. };
+        let bend = |d: &vec3| if reflected { d.reflect(&n) } else { d.refract(&n, eta) };
+
+        // Whatever the material did to the main ray (fuzz, roughness) moves the neighbours too
+        let ideal = bend(&d_in);
+        Some(ray_differential {
+            rx_origin: self.p + self.footprint.dpdx,
+            rx_dir: d_out + bend(&diff.rx_dir.unit_vec()) - ideal,
+            ry_origin: self.p + self.footprint.dpdy,
+            ry_dir: d_out + bend(&diff.ry_dir.unit_vec()) - ideal,
+        })
+    }
+
+    /// Orthonormal frame around n, u follows the tangent and v the bitangent when there are some
+    pub fn shading_frame(&self) -> onb {
+        let t = self.tangent - self.n * self.n.dot(&self.tangent);
+        if t.length_squared() < 1e-12 { return onb::build_from_w(&self.n) }
+        let u = t.unit_vec();
+        let v = self.n.cross(&u);
+        // Keep v on the side of increasing v even on back faces, normal maps expect it
+        let v = if v.dot(&self.bitangent) < 0. { v * -1. } else { v };
+        onb { u, v, w: self.n }
     }
 }
 
@@ -53,6 +147,51 @@ pub trait Hittable: Send + Sync {
         box1.min.v[axis] < box2.min.v[axis]
     }
 
+    // Light sampling, only objects that can act as area lights override these
+
+    /// Solid angle pdf of random() generating dir from origin, 0 if it can't be sampled
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 {
+        0.
+    }
+
+    /// Random direction (not normalized) from origin towards a point of the object
+    fn random(&self, origin: &point3) -> vec3 {
+        vec3::from(1., 0., 0.)
+    }
+
+    /// Lights without size (objects/lights.rs), reached only by sample_delta() and never by pdf_value()
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// Light of a delta light arriving at p: (direction towards it, distance, incoming light already
+    /// divided by the distance squared). None if p is out of its reach
+    fn sample_delta(&self, p: &point3) -> Option<(vec3, f64, colorRGB)> {
+        None
+    }
+
+    /// Surface area, what Diffuse_Emissive::shape_with_power spreads the power over. 0 if unknown
+    fn area(&self) -> f64 {
+        0.
+    }
+
+    /// Where a light is and what it emits for light_bvh and power_light_sampler,
+    /// None for objects that don't emit or have no bounds (picked apart from the rest)
+    fn light_bounds(&self) -> Option<light_bounds> {
+        None
+    }
+
+    /// Area lights for bdpt: a random point of the surface as a hit (p, outward normal, uv, mat)
+    /// and its pdf by area. None if the object can't be sampled like that
+    fn sample_surface(&self) -> Option<(hit_record, f64)> {
+        None
+    }
+
+    /// Pdf by area of sample_surface() giving p, a point of the surface
+    fn surface_pdf(&self, p: &point3) -> f64 {
+        0.
+    }
+
     //fn get_uv(&self, hit_pos: &point3, uv: &mut point2);
 }
 
@@ -4,11 +4,14 @@ use std::sync::Mutex;
 use crate::rtow_math::ray::*;
 use crate::objects::aabb::*;
 use crate::rtow_math::rng::*;
+use rand::Rng;
 
 pub struct hittable_list {
     pub obj_list: Box<Vec<Arc<dyn Hittable>>>,
     bvh_start: Arc<dyn Hittable>,
     bvh_node_list: Box<Vec<Arc<dyn Hittable>>>,
+    // Objects without aabb (infinite planes...), checked one by one after the bvh
+    unbounded_list: Box<Vec<Arc<dyn Hittable>>>,
     pub num_nodes: i32,
 }
 
@@ -18,6 +21,7 @@ impl hittable_list {
             obj_list: Box::new(Vec::new()),
             bvh_start: Arc::new(bvh_node::new_empty()),
             bvh_node_list: Box::new(Vec::new()),
+            unbounded_list: Box::new(Vec::new()),
             num_nodes: 0,
         }
     }
@@ -48,6 +52,14 @@ impl hittable_list {
             closest = temp_rec.t;
             *rec = temp_rec.clone();
         }
+
+        for obj in self.unbounded_list.as_ref() {
+            if(obj.hit(r, t_min, closest, &mut temp_rec) && temp_rec.t < closest) {
+                hit_anything = true;
+                closest = temp_rec.t;
+                *rec = temp_rec.clone();
+            }
+        }
         
         hit_anything
     }
@@ -60,7 +72,8 @@ impl hittable_list {
         let mut check: bool;
         for obj in self.obj_list.as_ref() {
             (check, temp_aabb) = obj.get_aabb(time0, time1);
-            if !check { continue };
+            // Anything unbounded makes the whole list unbounded
+            if !check { return (false, aabb::new()) };
             ret_aabb = if first_b {temp_aabb} else {aabb::from_2_aabb(ret_aabb, temp_aabb)};
             first_b = false;
         }
@@ -68,7 +81,16 @@ impl hittable_list {
     }
 
     pub fn construct_bvh(&mut self, time0: f64, time1: f64) {
-        let arc_node = Arc::new(bvh_node::new(&mut self.obj_list[..], time0, time1, &mut self.num_nodes, &mut self.bvh_node_list, 0));
+        // Unbounded objects can't be sorted into the bvh, keep them apart
+        let mut bounded: Vec<Arc<dyn Hittable>> = Vec::new();
+        self.unbounded_list.clear();
+        for obj in self.obj_list.iter() {
+            if obj.get_aabb(time0, time1).0 { bounded.push(Arc::clone(obj)) }
+            else { self.unbounded_list.push(Arc::clone(obj)) }
+        }
+        if bounded.len() == 0 { return }
+
+        let arc_node = Arc::new(bvh_node::new(&mut bounded[..], time0, time1, &mut self.num_nodes, &mut self.bvh_node_list, 0));
         self.bvh_node_list.push(arc_node);
         let len = self.bvh_node_list.len();
         self.bvh_start = Arc::clone(&self.bvh_node_list[len-1]);
@@ -82,6 +104,18 @@ impl Hittable for hittable_list {
     fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
         self.compute_full_aabb(time0, time1)
     }
+
+    // Used as a list of lights, each object is picked with the same probability
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 {
+        if self.obj_list.len() == 0 { return 0. }
+        let weight = 1. / self.obj_list.len() as f64;
+        self.obj_list.iter().map(|obj| weight * obj.pdf_value(origin, dir)).sum()
+    }
+
+    fn random(&self, origin: &point3) -> vec3 {
+        let idx = rand::thread_rng().gen_range(0..self.obj_list.len());
+        self.obj_list[idx].random(origin)
+    }
 }
 
 use crate::materials::*;
@@ -108,6 +142,8 @@ impl bvh_node {
                 alpha: 0.1, 
                 index_refr: 0.,
                 tex: Arc::new(Solid_Color::new()),
+                absorption: colorRGB::new(),
+                dispersion: Dispersion::Constant,
             }),
             internal_depth: 0,
         }
@@ -0,0 +1,462 @@
+use crate::materials::prelude::*;
+use crate::rtow_math::prelude::*;
+use crate::objects::prelude::*;
+use std::sync::Arc;
+
+// Which light to sample from a shading point. With a few lights picking any of them with the same
+// probability works, with hundreds of small ones most picks land on lights too far away or facing
+// elsewhere to matter. power_light_sampler picks by emitted power, light_bvh by an estimate of what
+// each light brings to the shading point (the light BVH of PBRT-v4: boxes plus cones of emission).
+// Lights without bounds (environment, sky, directional lights) are picked apart, uniformly.
+
+/// What a light sampler knows of a light: where it is, how much it emits and towards where
+#[derive(Clone)]
+pub struct light_bounds {
+    pub bounds: aabb,
+    // Emitted power, only compared between lights so any scale works as long as all use it
+    pub phi: f64,
+    // Cone holding the normals of the emitter: axis w and half angle theta_o (as its cosine).
+    // theta_e is how far from the normals light still leaves, pi/2 for area lights
+    pub w: vec3,
+    pub cos_theta_o: f64,
+    pub cos_theta_e: f64,
+    pub two_sided: bool,
+}
+
+impl light_bounds {
+    pub fn centroid(&self) -> point3 {
+        (self.bounds.min + self.bounds.max) * 0.5
+    }
+
+    /// Bounds of both, the cones are merged into one holding both
+    pub fn union(&self, other: &light_bounds) -> light_bounds {
+        if self.phi <= 0. { return other.clone() }
+        if other.phi <= 0. { return self.clone() }
+        let (w, cos_theta_o) = cone_union(&self.w, self.cos_theta_o, &other.w, other.cos_theta_o);
+        light_bounds {
+            bounds: aabb::from_2_aabb(self.bounds.clone(), other.bounds.clone()),
+            phi: self.phi + other.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    /// Upper estimate of the light reaching p: power over the squared distance, times the cosine
+    /// of the smallest angle between the direction to p and a direction the lights emit towards.
+    /// Never 0 if some of the light can get to p
+    pub fn importance(&self, p: &point3) -> f64 {
+        if self.phi <= 0. { return 0. }
+        let to_p = *p - self.centroid();
+        let diag = self.bounds.max - self.bounds.min;
+        let dist2 = to_p.length_squared();
+        // Inside or close to the box the distance says little, keep it from blowing up
+        let d2 = dist2.max(diag.length() / 2.).max(1e-8);
+
+        let mut cos_theta_w = if dist2 > 0. { self.w.dot(&(to_p / dist2.sqrt())) } else { 1. };
+        if self.two_sided { cos_theta_w = cos_theta_w.abs() }
+
+        // Cone of directions the box covers seen from p, all of them from inside
+        let radius2 = diag.length_squared() / 4.;
+        let cos_theta_b = if dist2 < radius2 { -1. } else { (1. - radius2 / dist2).max(0.).sqrt() };
+
+        let theta = (safe_acos(cos_theta_w) - safe_acos(self.cos_theta_o) - safe_acos(cos_theta_b)).max(0.);
+        let cos_theta = theta.cos();
+        if cos_theta <= self.cos_theta_e { return 0. }
+        self.phi * cos_theta / d2
+    }
+}
+
+fn safe_acos(x: f64) -> f64 {
+    x.clamp(-1., 1.).acos()
+}
+
+/// Smallest cone holding the cones (w_a, cos_a) and (w_b, cos_b)
+fn cone_union(w_a: &vec3, cos_a: f64, w_b: &vec3, cos_b: f64) -> (vec3, f64) {
+    if cos_a <= -1. || cos_b <= -1. { return (*w_a, -1.) }
+    let (theta_a, theta_b) = (safe_acos(cos_a), safe_acos(cos_b));
+    let theta_d = safe_acos(w_a.dot(w_b));
+    // One already holds the other
+    if (theta_d + theta_b).min(pi) <= theta_a { return (*w_a, cos_a) }
+    if (theta_d + theta_a).min(pi) <= theta_b { return (*w_b, cos_b) }
+
+    let theta_o = (theta_a + theta_d + theta_b) / 2.;
+    if theta_o >= pi { return (*w_a, -1.) }
+
+    // Turn w_a towards w_b by theta_o - theta_a, around their cross product
+    let axis = w_a.cross(w_b);
+    if axis.length_squared() < 1e-12 { return (*w_a, -1.) }
+    let axis = axis.unit_vec();
+    let theta_r = theta_o - theta_a;
+    let w = *w_a * theta_r.cos() + axis.cross(w_a) * theta_r.sin();
+    (w.unit_vec(), theta_o.cos())
+}
+
+/// Picks lights for light_sampling. Indices returned by sample_light() and taken by light_pmf()
+/// are positions in lights()
+pub trait LightSampler: Send + Sync {
+    fn lights(&self) -> &[Arc<dyn Hittable>];
+
+    /// Light to sample from p for a uniform u, and the probability it had to be picked
+    fn sample_light(&self, p: &point3, u: f64) -> Option<(usize, f64)>;
+
+    /// Probability of sample_light() picking lights()[idx] from p
+    fn light_pmf(&self, p: &point3, idx: usize) -> f64;
+
+    /// Pdf of getting dir from p by picking a light and sampling it, to weight bsdf samples with
+    fn light_pdf(&self, p: &point3, dir: &vec3) -> f64 {
+        let mut pdf = 0.;
+        for (idx, light) in self.lights().iter().enumerate() {
+            let light_pdf = light.pdf_value(p, dir);
+            if light_pdf > 0. { pdf += self.light_pmf(p, idx) * light_pdf }
+        }
+        pdf
+    }
+}
+
+/// A plain lights list picks each light with the same probability
+impl LightSampler for hittable_list {
+    fn lights(&self) -> &[Arc<dyn Hittable>] {
+        &self.obj_list
+    }
+
+    fn sample_light(&self, p: &point3, u: f64) -> Option<(usize, f64)> {
+        let count = self.obj_list.len();
+        if count == 0 { return None }
+        Some((((u * count as f64) as usize).min(count - 1), 1. / count as f64))
+    }
+
+    fn light_pmf(&self, p: &point3, idx: usize) -> f64 {
+        1. / self.obj_list.len() as f64
+    }
+}
+
+/// Probability of picking one of the lights without bounds: each of them counts as much as all the
+/// others together, as in PBRT
+fn unbounded_share(unbounded: usize, any_bounded: bool) -> f64 {
+    if unbounded == 0 { return 0. }
+    if !any_bounded { return 1. }
+    unbounded as f64 / (unbounded as f64 + 1.)
+}
+
+/// Picks lights proportionally to their power, wherever the shading point is.
+/// Cheap and much better than uniform when the lights differ a lot in power
+pub struct power_light_sampler {
+    lights: Vec<Arc<dyn Hittable>>,
+    unbounded: Vec<usize>,
+    // Light of each step of distribution, and the step of each light (None if unbounded)
+    bounded: Vec<usize>,
+    slot: Vec<Option<usize>>,
+    distribution: Option<distribution_1d>,
+}
+
+impl power_light_sampler {
+    pub fn from(lights: &hittable_list) -> power_light_sampler {
+        let lights: Vec<Arc<dyn Hittable>> = lights.obj_list.iter().map(Arc::clone).collect();
+        let (mut unbounded, mut bounded, mut slot, mut power) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for (idx, light) in lights.iter().enumerate() {
+            match light.light_bounds() {
+                Some(lb) => {
+                    slot.push(Some(bounded.len()));
+                    bounded.push(idx);
+                    power.push(lb.phi);
+                },
+                None => {
+                    slot.push(None);
+                    unbounded.push(idx);
+                },
+            }
+        }
+        let distribution = if power.is_empty() { None } else { Some(distribution_1d::from(power)) };
+        power_light_sampler { lights, unbounded, bounded, slot, distribution }
+    }
+
+    fn unbounded_share(&self) -> f64 {
+        unbounded_share(self.unbounded.len(), self.distribution.is_some())
+    }
+}
+
+impl LightSampler for power_light_sampler {
+    fn lights(&self) -> &[Arc<dyn Hittable>] {
+        &self.lights
+    }
+
+    fn sample_light(&self, p: &point3, u: f64) -> Option<(usize, f64)> {
+        if self.unbounded.is_empty() && self.distribution.is_none() { return None }
+        let p_unbounded = self.unbounded_share();
+        if u < p_unbounded {
+            let count = self.unbounded.len();
+            let i = ((u / p_unbounded * count as f64) as usize).min(count - 1);
+            return Some((self.unbounded[i], p_unbounded / count as f64))
+        }
+        let distribution = self.distribution.as_ref()?;
+        let step = distribution.sample_discrete((u - p_unbounded) / (1. - p_unbounded));
+        Some((self.bounded[step], (1. - p_unbounded) * distribution.discrete_pdf(step)))
+    }
+
+    fn light_pmf(&self, p: &point3, idx: usize) -> f64 {
+        let p_unbounded = self.unbounded_share();
+        match (self.slot[idx], &self.distribution) {
+            (Some(step), Some(distribution)) => (1. - p_unbounded) * distribution.discrete_pdf(step),
+            _ if self.unbounded.is_empty() => 0.,
+            _ => p_unbounded / self.unbounded.len() as f64,
+        }
+    }
+}
+
+/// Node of light_bvh, children for inner nodes or the light of a leaf
+struct light_bvh_node {
+    lb: light_bounds,
+    children: Option<(usize, usize)>,
+    light: usize,
+    parent: Option<usize>,
+}
+
+/// Hierarchy of the lights that picks them by their importance at the shading point: at each node
+/// the child that seems to bring more light to it is more likely to be followed. Close and facing
+/// lights get most samples, even among thousands
+pub struct light_bvh {
+    lights: Vec<Arc<dyn Hittable>>,
+    nodes: Vec<light_bvh_node>,
+    root: Option<usize>,
+    unbounded: Vec<usize>,
+    // Leaf of each light, None for the unbounded ones
+    leaf: Vec<Option<usize>>,
+}
+
+// Buckets tried along each axis when splitting a node
+const LIGHT_BVH_BUCKETS: usize = 12;
+
+impl light_bvh {
+    pub fn from(lights: &hittable_list) -> light_bvh {
+        let lights: Vec<Arc<dyn Hittable>> = lights.obj_list.iter().map(Arc::clone).collect();
+        let mut bvh = light_bvh { nodes: Vec::new(), root: None, unbounded: Vec::new(), leaf: vec![None; lights.len()], lights };
+
+        let mut items = Vec::new();
+        for (idx, light) in bvh.lights.iter().enumerate() {
+            match light.light_bounds() {
+                // Lights that can't emit anything would only take samples
+                Some(lb) => if lb.phi > 0. { items.push((idx, lb)) },
+                None => bvh.unbounded.push(idx),
+            }
+        }
+        if !items.is_empty() {
+            bvh.root = Some(bvh.build(&mut items));
+        }
+        bvh
+    }
+
+    fn build(&mut self, items: &mut [(usize, light_bounds)]) -> usize {
+        if items.len() == 1 {
+            let (light, lb) = items[0].clone();
+            self.nodes.push(light_bvh_node { lb, children: None, light, parent: None });
+            self.leaf[light] = Some(self.nodes.len() - 1);
+            return self.nodes.len() - 1
+        }
+
+        let mid = light_bvh::split(items);
+        let (left_items, right_items) = items.split_at_mut(mid);
+        let left = self.build(left_items);
+        let right = self.build(right_items);
+
+        let lb = self.nodes[left].lb.union(&self.nodes[right].lb);
+        self.nodes.push(light_bvh_node { lb, children: Some((left, right)), light: 0, parent: None });
+        let node = self.nodes.len() - 1;
+        self.nodes[left].parent = Some(node);
+        self.nodes[right].parent = Some(node);
+        node
+    }
+
+    /// Sorts items into two groups and returns where the second starts, at the bucket boundary
+    /// with the lowest cost along any axis. Falls back to halves when the lights share a centroid
+    fn split(items: &mut [(usize, light_bounds)]) -> usize {
+        let mut all = items[0].1.clone();
+        let (mut cmin, mut cmax) = (point3::inf_max(), point3::inf_min());
+        for (_, lb) in items.iter() {
+            all = all.union(lb);
+            let c = lb.centroid();
+            for i in 0..3 {
+                cmin.v[i] = cmin.v[i].min(c.v[i]);
+                cmax.v[i] = cmax.v[i].max(c.v[i]);
+            }
+        }
+
+        let extent = all.bounds.max - all.bounds.min;
+        let max_extent = extent.v[0].max(extent.v[1]).max(extent.v[2]);
+        let bucket_of = |lb: &light_bounds, axis: usize| -> usize {
+            let t = (lb.centroid().v[axis] - cmin.v[axis]) / (cmax.v[axis] - cmin.v[axis]);
+            ((t * LIGHT_BVH_BUCKETS as f64) as usize).min(LIGHT_BVH_BUCKETS - 1)
+        };
+
+        let mut best: Option<(f64, usize, usize)> = None;
+        for axis in 0..3 {
+            if cmax.v[axis] <= cmin.v[axis] { continue }
+            let mut buckets: Vec<Option<light_bounds>> = vec![None; LIGHT_BVH_BUCKETS];
+            for (_, lb) in items.iter() {
+                let b = bucket_of(lb, axis);
+                buckets[b] = Some(match &buckets[b] { Some(acc) => acc.union(lb), None => lb.clone() });
+            }
+            // Thin boxes along the axis are stretched, long splits shouldn't look cheaper
+            let kr = if extent.v[axis] > 0. { max_extent / extent.v[axis] } else { 1. };
+            for split in 1..LIGHT_BVH_BUCKETS {
+                let (below, above) = (merge(&buckets[..split]), merge(&buckets[split..]));
+                let (Some(below), Some(above)) = (below, above) else { continue };
+                let cost = kr * (split_cost(&below) + split_cost(&above));
+                if best.map_or(true, |(c, _, _)| cost < c) { best = Some((cost, axis, split)) }
+            }
+        }
+
+        let mid = match best {
+            Some((_, axis, split)) => {
+                items.sort_by_key(|(_, lb)| bucket_of(lb, axis) >= split);
+                items.iter().filter(|(_, lb)| bucket_of(lb, axis) < split).count()
+            },
+            None => items.len() / 2,
+        };
+        mid.clamp(1, items.len() - 1)
+    }
+
+    fn unbounded_share(&self) -> f64 {
+        unbounded_share(self.unbounded.len(), self.root.is_some())
+    }
+
+    /// Probability of going from node to each of its children at p
+    fn child_probs(&self, p: &point3, left: usize, right: usize) -> Option<(f64, f64)> {
+        let (il, ir) = (self.nodes[left].lb.importance(p), self.nodes[right].lb.importance(p));
+        if il + ir <= 0. { return None }
+        Some((il / (il + ir), ir / (il + ir)))
+    }
+}
+
+fn merge(buckets: &[Option<light_bounds>]) -> Option<light_bounds> {
+    buckets.iter().flatten().fold(None, |acc: Option<light_bounds>, lb| Some(match acc { Some(a) => a.union(lb), None => lb.clone() }))
+}
+
+/// Surface area orientation heuristic of PBRT-v4: power times the solid angle the cone of emission
+/// covers times the area of the box
+fn split_cost(lb: &light_bounds) -> f64 {
+    let theta_o = safe_acos(lb.cos_theta_o);
+    let theta_e = safe_acos(lb.cos_theta_e);
+    let theta_w = (theta_o + theta_e).min(pi);
+    let sin_theta_o = (1. - lb.cos_theta_o * lb.cos_theta_o).max(0.).sqrt();
+    let m_omega = 2. * pi * (1. - lb.cos_theta_o)
+        + pi / 2. * (2. * theta_w * sin_theta_o - (theta_o - 2. * theta_w).cos() - 2. * theta_o * sin_theta_o + lb.cos_theta_o);
+    let d = lb.bounds.max - lb.bounds.min;
+    let area = 2. * (d.v[0] * d.v[1] + d.v[1] * d.v[2] + d.v[2] * d.v[0]);
+    lb.phi * m_omega * area
+}
+
+impl LightSampler for light_bvh {
+    fn lights(&self) -> &[Arc<dyn Hittable>] {
+        &self.lights
+    }
+
+    fn sample_light(&self, p: &point3, u: f64) -> Option<(usize, f64)> {
+        // No lights, or none of them gives off anything
+        if self.unbounded.is_empty() && self.root.is_none() { return None }
+        let p_unbounded = self.unbounded_share();
+        if u < p_unbounded {
+            let count = self.unbounded.len();
+            let i = ((u / p_unbounded * count as f64) as usize).min(count - 1);
+            return Some((self.unbounded[i], p_unbounded / count as f64))
+        }
+
+        let mut node = self.root?;
+        if self.nodes[node].lb.importance(p) <= 0. { return None }
+        let mut u = (u - p_unbounded) / (1. - p_unbounded);
+        let mut pmf = 1. - p_unbounded;
+        // Down the tree, u is reused by rescaling it into the branch taken
+        while let Some((left, right)) = self.nodes[node].children {
+            let (p_left, p_right) = self.child_probs(p, left, right)?;
+            if u < p_left {
+                node = left;
+                u /= p_left;
+                pmf *= p_left;
+            } else {
+                node = right;
+                u = ((u - p_left) / p_right).min(1. - f64::EPSILON);
+                pmf *= p_right;
+            }
+        }
+        Some((self.nodes[node].light, pmf))
+    }
+
+    fn light_pmf(&self, p: &point3, idx: usize) -> f64 {
+        let p_unbounded = self.unbounded_share();
+        let Some(mut node) = self.leaf[idx] else {
+            return if self.unbounded.contains(&idx) { p_unbounded / self.unbounded.len() as f64 } else { 0. }
+        };
+
+        // Up the tree, the probability of each branch on the way
+        let mut pmf = 1. - p_unbounded;
+        while let Some(parent) = self.nodes[node].parent {
+            let Some((left, right)) = self.nodes[parent].children else { break };
+            let Some((p_left, p_right)) = self.child_probs(p, left, right) else { return 0. };
+            pmf *= if node == left { p_left } else { p_right };
+            node = parent;
+        }
+        if self.nodes[node].lb.importance(p) <= 0. { return 0. }
+        pmf
+    }
+
+    // Only the branches whose box the ray goes through can have a light it reaches
+    fn light_pdf(&self, p: &point3, dir: &vec3) -> f64 {
+        let p_unbounded = self.unbounded_share();
+        let mut pdf: f64 = self.unbounded.iter().map(|idx| self.lights[*idx].pdf_value(p, dir)).sum::<f64>() * p_unbounded / self.unbounded.len().max(1) as f64;
+
+        let Some(root) = self.root else { return pdf };
+        if self.nodes[root].lb.importance(p) <= 0. { return pdf }
+        let r = ray::from(*p, *dir);
+        let mut stack = vec![(root, 1. - p_unbounded)];
+        while let Some((node, pmf)) = stack.pop() {
+            if !self.nodes[node].lb.bounds.hit_fast(&r, 0.0001, INFINITY) { continue }
+            match self.nodes[node].children {
+                None => pdf += pmf * self.lights[self.nodes[node].light].pdf_value(p, dir),
+                Some((left, right)) => {
+                    let Some((p_left, p_right)) = self.child_probs(p, left, right) else { continue };
+                    if p_left > 0. { stack.push((left, pmf * p_left)) }
+                    if p_right > 0. { stack.push((right, pmf * p_right)) }
+                },
+            }
+        }
+        pdf
+    }
+}
+
+#[test]
+fn light_pmf_matches_sample_light() {
+    let glow = |s: f64| -> Arc<dyn Material> { Arc::new(Diffuse_Emissive { albedo: colorRGB::one() * s, ..Diffuse_Emissive::new(Arc::new(Solid_Color::from(1., 1., 1.))) }) };
+    let mut lights = hittable_list::new();
+    lights.obj_list.push(Arc::new(sphere::from_mat(point3::from(0., 2., 0.), 0.5, glow(1.))));
+    lights.obj_list.push(Arc::new(sphere::from_mat(point3::from(3., 1., -2.), 0.2, glow(8.))));
+    lights.obj_list.push(Arc::new(quad::from(point3::from(-2., 3., -2.), vec3::from(1., 0., 0.), vec3::from(0., 0., 1.), glow(4.))));
+    lights.obj_list.push(Arc::new(quad::from(point3::from(5., 0., 5.), vec3::from(0., 1., 0.), vec3::from(0., 0., 1.), glow(2.))));
+    let mut with_sun = hittable_list::new();
+    with_sun.obj_list = lights.obj_list.clone();
+    with_sun.obj_list.push(Arc::new(sun_disk::from(vec3::from(1., 1., 0.), 0.01)));
+
+    for list in [&lights, &with_sun] {
+        let samplers: [Box<dyn LightSampler>; 2] = [Box::new(power_light_sampler::from(list)), Box::new(light_bvh::from(list))];
+        for sampler in samplers.iter() {
+            for p in [point3::new(), point3::from(2., 0.5, 1.), point3::from(-1., 2.5, -1.5)] {
+                let total: f64 = (0..list.obj_list.len()).map(|idx| sampler.light_pmf(&p, idx)).sum();
+                assert!((total - 1.).abs() < 1e-9);
+                for k in 0..1000 {
+                    let (idx, pmf) = sampler.sample_light(&p, (k as f64 + 0.5) / 1000.).unwrap();
+                    assert!((pmf - sampler.light_pmf(&p, idx)).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    // Nothing to pick, without lights or with lights that give off nothing
+    let mut dark = hittable_list::new();
+    dark.obj_list.push(Arc::new(sphere::from_mat(point3::new(), 1., glow(0.))));
+    for list in [&hittable_list::new(), &dark] {
+        assert!(light_bvh::from(list).sample_light(&point3::new(), 0.5).is_none());
+    }
+    let empty = hittable_list::new();
+    assert!(power_light_sampler::from(&empty).sample_light(&point3::new(), 0.5).is_none());
+    assert!(empty.sample_light(&point3::new(), 0.5).is_none());
+}
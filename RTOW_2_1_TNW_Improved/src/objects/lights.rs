@@ -1,7 +1,12 @@
 use crate::rtow_math::vec3::*;
 use crate::rtow_math::ray::*;
+use crate::rtow_math::defines::*;
 use crate::objects::hit::*;
+use crate::objects::light_sampler::*;
 
+/// Lights without any size, delta lights: no ray can hit them and no bsdf sample can find them,
+/// light_sampling only reaches them through light_sample(). Put them in the lights list, not in the world.
+/// Point and spot lights fall off with the square of the distance, a directional light has none.
 pub enum LightType {
     Point,
     Directional,
@@ -10,73 +15,124 @@ pub enum LightType {
 
 pub struct light {
     pub center: point3,
+    // Radiant intensity (per steradian) for points and spots, irradiance for directional lights
     pub intensity: f64,
     pub color: colorRGB,
+    // Where the light points to, for directional lights and spotlights
     pub direction: vec3,
     pub ltype: LightType,
+    // Spotlights only: half angle of the cone in degrees, and where the edge starts to fade
+    pub cone_angle: f64,
+    pub falloff_angle: f64,
 }
 
 impl light {
     pub fn new_point(center: point3, intensity: f64, color: colorRGB) -> light {
-        light { center, intensity, color, direction: vec3::new(), ltype: LightType::Point }
+        light { center, intensity, color, direction: vec3::new(), ltype: LightType::Point, cone_angle: 180., falloff_angle: 180. }
     }
 
     pub fn new_direction(direction: vec3, intensity: f64, color: colorRGB) -> light {
-        light { center: point3::new(), intensity, color, direction, ltype: LightType::Directional }
+        light { center: point3::new(), intensity, color, direction: direction.unit_vec(), ltype: LightType::Directional, cone_angle: 0., falloff_angle: 0. }
     }
 
+    /// 30 degree cone that fades from 20 degrees, other angles with ..light::new_spotlight()
     pub fn new_spotlight(center: point3, direction: vec3, intensity: f64, color: colorRGB) -> light {
-        light { center, intensity, color, direction, ltype: LightType::Spotlight }
+        light { center, intensity, color, direction: direction.unit_vec(), ltype: LightType::Spotlight, cone_angle: 30., falloff_angle: 20. }
     }
-    
-    pub fn hit_point(&self,r: &ray, t_min: f64, t_max: f64, rec:& mut hit_record ) -> bool {
-        let origin_center = r.origin - self.center;
-        let a = r.dir.length_squared();
-        let half_b = origin_center.dot(&r.dir);
-        let c = origin_center.dot(&origin_center) - self.intensity*self.intensity;
-        
-        let mut discriminant = half_b*half_b - a*c;
-        //discriminant = check_discriminant(discriminant, half_b, a);
-        if (discriminant < 0.) {return false}
-        
-        let sq_discr = discriminant.sqrt();
-        let mut root = (-half_b - sq_discr) / a;
-        if(root < t_min || root > t_max) {
-            root = (-half_b +sq_discr) / a;
-            if(root < t_min || t_max < root) {
-                return false
-            }
-        }
-
-        rec.t = root;
-        rec.p = r.at(rec.t);
-        rec.n = (rec.p - self.center) / self.intensity; // This is bad, only returns normal pointing outwards
-        // What if we need to differentiate between from and back face!
-        rec.set_face_normal(r, rec.n);
-        //rec.mat = Arc::clone(&self.mat);
 
-        true
+    /// Spotlight brightness away from its axis: full inside the falloff angle, smoothly to 0 at the cone
+    fn spot_falloff(&self, to_p: &vec3) -> f64 {
+        let cos = to_p.unit_vec().dot(&self.direction.unit_vec());
+        let cos_total = deg_to_rad(self.cone_angle).cos();
+        let cos_falloff = deg_to_rad(self.falloff_angle.min(self.cone_angle)).cos();
+        if cos < cos_total { return 0. }
+        if cos >= cos_falloff { return 1. }
+        let t = (cos - cos_total) / (cos_falloff - cos_total);
+        t * t * (3. - 2. * t)
     }
 }
 
 use crate::objects::aabb::*;
 
-use crate::objects::hittable_list::*;
-use std::sync::Arc;
-use std::sync::Mutex;
-
 impl Hittable for light {
     fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:& mut hit_record) -> bool {
-        
+        false
+    }
+
+    // No size, a bvh_node would have nothing to bound
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        (false, aabb::new())
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn sample_delta(&self, p: &point3) -> Option<(vec3, f64, colorRGB)> {
         match self.ltype {
-            LightType::Point => {
-                self.hit_point(r, t_min, t_max, rec)
+            LightType::Directional => Some((self.direction * -1., INFINITY, self.color * self.intensity)),
+            LightType::Point | LightType::Spotlight => {
+                let to_light = self.center - *p;
+                let dist2 = to_light.length_squared();
+                if dist2 <= 0. { return None }
+                let falloff = match self.ltype {
+                    LightType::Spotlight => self.spot_falloff(&(to_light * -1.)),
+                    _ => 1.,
+                };
+                if falloff <= 0. { return None }
+                Some((to_light, dist2.sqrt(), self.color * (self.intensity * falloff / dist2)))
             },
-            _ => false
         }
     }
 
-    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
-        (false, aabb::new()) // Will have to be solved by type...
+    // Directional lights are everywhere, picked with the other unbounded lights
+    fn light_bounds(&self) -> Option<light_bounds> {
+        let lum = self.intensity * (0.2126 * self.color.v[0] + 0.7152 * self.color.v[1] + 0.0722 * self.color.v[2]);
+        let bounds = aabb::from(self.center, self.center);
+        match self.ltype {
+            LightType::Directional => None,
+            LightType::Point => Some(light_bounds { bounds, phi: 4. * pi * lum, w: vec3::up(), cos_theta_o: -1., cos_theta_e: 0., two_sided: false }),
+            LightType::Spotlight => {
+                let (theta_end, theta_start) = (deg_to_rad(self.cone_angle), deg_to_rad(self.falloff_angle.min(self.cone_angle)));
+                let (cos_end, cos_start) = (theta_end.cos(), theta_start.cos());
+                let phi = lum * 2. * pi * ((1. - cos_start) + (cos_start - cos_end) / 2.);
+                Some(light_bounds { bounds, phi, w: self.direction.unit_vec(), cos_theta_o: cos_start, cos_theta_e: (theta_end - theta_start).cos(), two_sided: false })
+            },
+        }
+    }
+}
+
+#[test]
+fn spot_falloff_and_sample_delta() {
+    // Straight down, points at an angle theta from the axis at distance 1
+    let spot = light::new_spotlight(point3::from(0., 0., 0.), vec3::from(0., -1., 0.), 10., colorRGB::from(1., 1., 1.));
+    let at = |deg: f64| { let t = deg_to_rad(deg); point3::from(t.sin(), -t.cos(), 0.) };
+
+    assert_eq!(spot.spot_falloff(&at(0.)), 1.);
+    assert_eq!(spot.spot_falloff(&at(19.)), 1.);
+    let edge = spot.spot_falloff(&at(25.));
+    assert!(edge > 0. && edge < 1.);
+    assert_eq!(spot.spot_falloff(&at(31.)), 0.);
+    assert!(spot.sample_delta(&at(31.)).is_none());
+
+    let (dir, dist, incoming) = spot.sample_delta(&at(10.)).unwrap();
+    assert!((dist - 1.).abs() < 1e-12);
+    assert!((dir.unit_vec().dot(&(at(10.) * -1.)) - 1.).abs() < 1e-12);
+    assert!((incoming.v[0] - 10.).abs() < 1e-12);
+
+    // Inverse square: twice as far is four times dimmer
+    let point = light::new_point(point3::from(1., 2., 3.), 10., colorRGB::from(1., 0.5, 0.25));
+    for d in [0.5, 1., 3.] {
+        let near = point.sample_delta(&point3::from(1. + d, 2., 3.)).unwrap().2;
+        let far = point.sample_delta(&point3::from(1. + 2. * d, 2., 3.)).unwrap().2;
+        assert!((near.v[1] - 5. / (d * d)).abs() < 1e-9);
+        assert!((near.v[1] / far.v[1] - 4.).abs() < 1e-9);
     }
-}
\ No newline at end of file
+
+    // A directional light is the same everywhere
+    let sun = light::new_direction(vec3::from(0., -2., 0.), 3., colorRGB::from(1., 1., 1.));
+    let (dir, dist, incoming) = sun.sample_delta(&point3::from(5., -7., 2.)).unwrap();
+    assert!(dist.is_infinite());
+    assert!((dir.v[1] - 1.).abs() < 1e-12);
+    assert!((incoming.v[2] - 3.).abs() < 1e-12);
+}
@@ -7,6 +7,14 @@ pub mod rectangles;
 pub mod rtow_box;
 pub mod transformed;
 pub mod volumes;
+pub mod quadrics;
+pub mod quad;
+pub mod sdf;
+pub mod heightfield;
+pub mod cutout;
+pub mod environment;
+pub mod sky;
+pub mod light_sampler;
 
 
 pub mod prelude;
\ No newline at end of file
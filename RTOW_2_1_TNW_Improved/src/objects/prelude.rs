@@ -6,4 +6,12 @@ pub use crate::objects::hittable_list::*;
 pub use crate::objects::rectangles::*;
 pub use crate::objects::rtow_box::*;
 pub use crate::objects::transformed::*;
-pub use crate::objects::volumes::*;
\ No newline at end of file
+pub use crate::objects::volumes::*;
+pub use crate::objects::quadrics::*;
+pub use crate::objects::quad::*;
+pub use crate::objects::sdf::*;
+pub use crate::objects::heightfield::*;
+pub use crate::objects::cutout::*;
+pub use crate::objects::environment::*;
+pub use crate::objects::sky::*;
+pub use crate::objects::light_sampler::*;
\ No newline at end of file
@@ -0,0 +1,161 @@
+use crate::materials::prelude::*;
+use std::sync::Arc;
+use crate::rtow_math::prelude::*;
+use crate::objects::prelude::*;
+
+/// Parallelogram from a corner q and two edges u, v, it can face any direction
+/// unlike xy_rect/xz_rect/yz_rect. uv are the planar coordinates along u and v.
+/// The normal follows the right hand rule: u x v
+pub struct quad {
+    q: point3,
+    u: vec3,
+    v: vec3,
+    // w = n / (n.n) with n = u x v, projects a point of the plane into (alpha, beta)
+    w: vec3,
+    normal: vec3,
+    d: f64,
+    area: f64,
+    mat: Arc<dyn Material>,
+}
+
+impl quad {
+    pub fn from(q: point3, u: vec3, v: vec3, mat: Arc<dyn Material>) -> quad {
+        let n = u.cross(&v);
+        let normal = n.unit_vec();
+        quad {
+            q, u, v,
+            w: n / n.dot(&n),
+            normal,
+            d: normal.dot(&q),
+            area: n.length(),
+            mat,
+        }
+    }
+
+    /// Quad centered at center, same as the axis aligned rects but in any orientation
+    pub fn centered(center: point3, u: vec3, v: vec3, mat: Arc<dyn Material>) -> quad {
+        quad::from(center - u / 2. - v / 2., u, v, mat)
+    }
+}
+
+unsafe impl Sync for quad{}
+unsafe impl Send for quad{}
+
+impl Hittable for quad {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        let denom = self.normal.dot(&r.dir);
+        // Parallel to the plane
+        if denom.abs() < 1e-8 { return false }
+
+        let t = (self.d - self.normal.dot(&r.origin)) / denom;
+        if t < t_min || t > t_max { return false }
+
+        // Planar coordinates of the hit inside the parallelogram
+        let p = r.at(t);
+        let planar = p - self.q;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if alpha < 0. || alpha > 1. || beta < 0. || beta > 1. { return false }
+
+        rec.uv.v[0] = alpha;
+        rec.uv.v[1] = beta;
+        rec.t = t;
+        rec.p = p;
+        rec.set_face_normal(r, self.normal);
+        rec.set_tangents(self.u, self.v);
+        rec.mat = Arc::clone(&self.mat);
+
+        true
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        let mut min = point3::inf_max();
+        let mut max = point3::inf_min();
+        for corner in [self.q, self.q + self.u, self.q + self.v, self.q + self.u + self.v] {
+            for i in 0..3 {
+                min.v[i] = min.v[i].min(corner.v[i]);
+                max.v[i] = max.v[i].max(corner.v[i]);
+            }
+        }
+        // Pad so flat quads don't get a 0 width box
+        for i in 0..3 {
+            if max.v[i] - min.v[i] < 0.0001 {
+                min.v[i] -= 0.0001;
+                max.v[i] += 0.0001;
+            }
+        }
+        (true, aabb::from(min, max))
+    }
+
+    // Area light: uniform point on the quad, pdf converted from area to solid angle
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 {
+        let mut rec = hit_record::new();
+        if !self.hit(&ray::from(*origin, *dir), 0.0001, INFINITY, &mut rec) { return 0. }
+
+        let dist_sq = rec.t * rec.t * dir.length_squared();
+        let cos = (dir.dot(&rec.n) / dir.length()).abs();
+        if cos < 1e-8 { return 0. }
+
+        dist_sq / (cos * self.area)
+    }
+
+    fn random(&self, origin: &point3) -> vec3 {
+        let p = self.q + self.u * rand_f64_r(0., 1.) + self.v * rand_f64_r(0., 1.);
+        p - *origin
+    }
+
+    fn sample_surface(&self) -> Option<(hit_record, f64)> {
+        let (alpha, beta) = (rand_f64(), rand_f64());
+        let mut rec = hit_record::new();
+        rec.p = self.q + self.u * alpha + self.v * beta;
+        rec.n = self.normal;
+        rec.uv = point2::from(alpha, beta);
+        rec.set_tangents(self.u, self.v);
+        rec.mat = Arc::clone(&self.mat);
+        Some((rec, 1. / self.area))
+    }
+
+    fn surface_pdf(&self, p: &point3) -> f64 {
+        1. / self.area
+    }
+
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    // Lambertian emitter, out of the front face or both
+    fn light_bounds(&self) -> Option<light_bounds> {
+        let (lum, two_sided) = self.mat.light_emission()?;
+        let faces = if two_sided { 2. } else { 1. };
+        Some(light_bounds {
+            bounds: self.get_aabb(0., 1.).1,
+            phi: lum * pi * self.area * faces,
+            w: self.normal,
+            cos_theta_o: 1.,
+            cos_theta_e: 0.,
+            two_sided,
+        })
+    }
+}
+
+/// 6 quads closing the box between 2 opposite corners, the quad version of aa_box
+pub fn quad_box(a: point3, b: point3, mat: Arc<dyn Material>) -> hittable_list {
+    let mut sides = hittable_list::new();
+
+    let min = point3::from(a.x().min(*b.x()), a.y().min(*b.y()), a.z().min(*b.z()));
+    let max = point3::from(a.x().max(*b.x()), a.y().max(*b.y()), a.z().max(*b.z()));
+
+    let dx = vec3::from(max.v[0] - min.v[0], 0., 0.);
+    let dy = vec3::from(0., max.v[1] - min.v[1], 0.);
+    let dz = vec3::from(0., 0., max.v[2] - min.v[2]);
+
+    sides.obj_list.push(Arc::new(quad::from(point3::from(min.v[0], min.v[1], max.v[2]), dx, dy, Arc::clone(&mat)))); // front
+    sides.obj_list.push(Arc::new(quad::from(point3::from(max.v[0], min.v[1], max.v[2]), dz * -1., dy, Arc::clone(&mat)))); // right
+    sides.obj_list.push(Arc::new(quad::from(point3::from(max.v[0], min.v[1], min.v[2]), dx * -1., dy, Arc::clone(&mat)))); // back
+    sides.obj_list.push(Arc::new(quad::from(point3::from(min.v[0], min.v[1], min.v[2]), dz, dy, Arc::clone(&mat)))); // left
+    sides.obj_list.push(Arc::new(quad::from(point3::from(min.v[0], max.v[1], max.v[2]), dx, dz * -1., Arc::clone(&mat)))); // top
+    sides.obj_list.push(Arc::new(quad::from(point3::from(min.v[0], min.v[1], min.v[2]), dx, dz, Arc::clone(&mat)))); // bottom
+
+    sides.construct_bvh(0., 1.);
+    sides
+}
@@ -0,0 +1,381 @@
+use crate::materials::prelude::*;
+use std::sync::Arc;
+use crate::rtow_math::prelude::*;
+use crate::objects::prelude::*;
+
+// All the shapes here are solved in their own local space (onb), where w is the axis of the shape.
+// The ray is moved into local space, solved there and the normal is brought back to world.
+// Being orthonormal, the t values are the same in both spaces.
+
+const FLAT_PAD: f64 = 0.0001;
+
+/// Angle around the local w axis mapped to [0,1]
+fn angle_uv(x: f64, y: f64) -> f64 {
+    (y.atan2(x) + pi) / (2. * pi)
+}
+
+/// Bounding box of a circle of radius r laying on the plane with normal n
+/// For each world axis, the extent is r * sin(angle between n and the axis)
+pub fn disk_aabb(center: point3, n: vec3, radius: f64) -> aabb {
+    let n = n.unit_vec();
+    let mut ext = vec3::new();
+    for i in 0..3 {
+        ext.v[i] = radius * (1. - n.v[i]*n.v[i]).max(0.).sqrt() + FLAT_PAD;
+    }
+    aabb::from(center - ext, center + ext)
+}
+
+/// Closest valid intersection found while checking the different parts of a shape
+struct local_hit {
+    t: f64,
+    n: vec3,
+    uv: point2,
+}
+
+impl local_hit {
+    fn keep_closest(best: &mut Option<local_hit>, t: f64, n: vec3, uv: point2) {
+        match best {
+            Some(b) if b.t <= t => (),
+            _ => *best = Some(local_hit{t, n, uv}),
+        }
+    }
+
+    fn fill(self, r: &ray, basis: &onb, mat: &Arc<dyn Material>, rec: &mut hit_record) {
+        rec.t = self.t;
+        rec.p = r.at(self.t);
+        rec.set_face_normal(r, basis.local(&self.n).unit_vec());
+        rec.mat = Arc::clone(mat);
+        rec.uv = self.uv;
+    }
+}
+
+/// Flat cap of a shape at height z (local), u around the axis and v from the center to the rim
+fn hit_cap(o: &vec3, d: &vec3, z: f64, radius: f64, out_n: vec3, t_min: f64, t_max: f64, best: &mut Option<local_hit>) {
+    if d.v[2].abs() < 1e-12 { return }
+    let t = (z - o.v[2]) / d.v[2];
+    if t < t_min || t > t_max { return }
+
+    let x = o.v[0] + d.v[0] * t;
+    let y = o.v[1] + d.v[1] * t;
+    let r2 = x*x + y*y;
+    if r2 > radius*radius { return }
+
+    local_hit::keep_closest(best, t, out_n, point2::from(angle_uv(x, y), r2.sqrt() / radius));
+}
+
+//===================================================================
+
+pub struct disk {
+    center: point3,
+    basis: onb,
+    radius: f64,
+    mat: Arc<dyn Material>,
+}
+
+impl disk {
+    pub fn from(center: point3, normal: vec3, radius: f64, mat: Arc<dyn Material>) -> disk {
+        disk { center, basis: onb::build_from_w(&normal), radius, mat }
+    }
+}
+
+unsafe impl Sync for disk{}
+unsafe impl Send for disk{}
+
+impl Hittable for disk {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        let o = self.basis.to_local(&(r.origin - self.center));
+        let d = self.basis.to_local(&r.dir);
+
+        let mut best = None;
+        hit_cap(&o, &d, 0., self.radius, vec3::from(0., 0., 1.), t_min, t_max, &mut best);
+
+        match best {
+            Some(h) => { h.fill(r, &self.basis, &self.mat, rec); true },
+            None => false,
+        }
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        (true, disk_aabb(self.center, self.basis.w, self.radius))
+    }
+}
+
+//===================================================================
+
+/// Disk with a hole in the middle, v goes from the inner to the outer radius
+pub struct annulus {
+    center: point3,
+    basis: onb,
+    inner: f64,
+    outer: f64,
+    mat: Arc<dyn Material>,
+}
+
+impl annulus {
+    pub fn from(center: point3, normal: vec3, inner: f64, outer: f64, mat: Arc<dyn Material>) -> annulus {
+        annulus { center, basis: onb::build_from_w(&normal), inner, outer, mat }
+    }
+}
+
+unsafe impl Sync for annulus{}
+unsafe impl Send for annulus{}
+
+impl Hittable for annulus {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        let o = self.basis.to_local(&(r.origin - self.center));
+        let d = self.basis.to_local(&r.dir);
+        if d.v[2].abs() < 1e-12 { return false }
+
+        let t = -o.v[2] / d.v[2];
+        if t < t_min || t > t_max { return false }
+
+        let x = o.v[0] + d.v[0] * t;
+        let y = o.v[1] + d.v[1] * t;
+        let dist = (x*x + y*y).sqrt();
+        if dist < self.inner || dist > self.outer { return false }
+
+        let uv = point2::from(angle_uv(x, y), (dist - self.inner) / (self.outer - self.inner));
+        local_hit{t, n: vec3::from(0., 0., 1.), uv}.fill(r, &self.basis, &self.mat, rec);
+        true
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        (true, disk_aabb(self.center, self.basis.w, self.outer))
+    }
+}
+
+//===================================================================
+
+/// Infinite plane, uv repeat every uv_scale units along the plane.
+/// It has no bounding box: get_aabb returns false and hittable_list::construct_bvh
+/// keeps it out of the BVH, checking it on every ray instead.
+pub struct plane {
+    point: point3,
+    basis: onb,
+    uv_scale: f64,
+    mat: Arc<dyn Material>,
+}
+
+impl plane {
+    pub fn from(point: point3, normal: vec3, mat: Arc<dyn Material>) -> plane {
+        plane::from_scaled(point, normal, 1., mat)
+    }
+
+    pub fn from_scaled(point: point3, normal: vec3, uv_scale: f64, mat: Arc<dyn Material>) -> plane {
+        plane { point, basis: onb::build_from_w(&normal), uv_scale, mat }
+    }
+}
+
+unsafe impl Sync for plane{}
+unsafe impl Send for plane{}
+
+impl Hittable for plane {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        let denom = self.basis.w.dot(&r.dir);
+        if denom.abs() < 1e-12 { return false }
+
+        let t = self.basis.w.dot(&(self.point - r.origin)) / denom;
+        if t < t_min || t > t_max { return false }
+
+        let local_p = self.basis.to_local(&(r.at(t) - self.point)) / self.uv_scale;
+        let uv = point2::from(local_p.v[0].rem_euclid(1.), local_p.v[1].rem_euclid(1.));
+        local_hit{t, n: vec3::from(0., 0., 1.), uv}.fill(r, &self.basis, &self.mat, rec);
+        true
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        (false, aabb::new())
+    }
+}
+
+//===================================================================
+
+/// Cylinder starting at base and growing height units along axis
+/// Body uv: u around the axis, v along it. Caps use the disk uv.
+pub struct cylinder {
+    base: point3,
+    basis: onb,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    mat: Arc<dyn Material>,
+}
+
+impl cylinder {
+    pub fn from(base: point3, axis: vec3, radius: f64, height: f64, capped: bool, mat: Arc<dyn Material>) -> cylinder {
+        cylinder { base, basis: onb::build_from_w(&axis), radius, height, capped, mat }
+    }
+}
+
+unsafe impl Sync for cylinder{}
+unsafe impl Send for cylinder{}
+
+impl Hittable for cylinder {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        let o = self.basis.to_local(&(r.origin - self.base));
+        let d = self.basis.to_local(&r.dir);
+
+        let mut best = None;
+
+        // x^2 + y^2 = R^2, z in [0, height]
+        let a = d.v[0]*d.v[0] + d.v[1]*d.v[1];
+        let b = 2. * (o.v[0]*d.v[0] + o.v[1]*d.v[1]);
+        let c = o.v[0]*o.v[0] + o.v[1]*o.v[1] - self.radius*self.radius;
+        for t in solve_quadratic(a, b, c) {
+            if t < t_min || t > t_max { continue }
+            let p = o + d * t;
+            if p.v[2] < 0. || p.v[2] > self.height { continue }
+            let uv = point2::from(angle_uv(p.v[0], p.v[1]), p.v[2] / self.height);
+            local_hit::keep_closest(&mut best, t, vec3::from(p.v[0], p.v[1], 0.), uv);
+        }
+
+        if self.capped {
+            hit_cap(&o, &d, 0., self.radius, vec3::from(0., 0., -1.), t_min, t_max, &mut best);
+            hit_cap(&o, &d, self.height, self.radius, vec3::from(0., 0., 1.), t_min, t_max, &mut best);
+        }
+
+        match best {
+            Some(h) => { h.fill(r, &self.basis, &self.mat, rec); true },
+            None => false,
+        }
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        let top = self.base + self.basis.w * self.height;
+        (true, aabb::from_2_aabb(
+            disk_aabb(self.base, self.basis.w, self.radius),
+            disk_aabb(top, self.basis.w, self.radius)))
+    }
+}
+
+//===================================================================
+
+/// Cone with its base at base and the apex height units along axis
+/// Body uv: u around the axis, v from base to apex. Cap uses the disk uv.
+pub struct cone {
+    base: point3,
+    basis: onb,
+    radius: f64,
+    height: f64,
+    capped: bool,
+    mat: Arc<dyn Material>,
+}
+
+impl cone {
+    pub fn from(base: point3, axis: vec3, radius: f64, height: f64, capped: bool, mat: Arc<dyn Material>) -> cone {
+        cone { base, basis: onb::build_from_w(&axis), radius, height, capped, mat }
+    }
+}
+
+unsafe impl Sync for cone{}
+unsafe impl Send for cone{}
+
+impl Hittable for cone {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        let o = self.basis.to_local(&(r.origin - self.base));
+        let d = self.basis.to_local(&r.dir);
+
+        let mut best = None;
+
+        // x^2 + y^2 = k^2 * (height - z)^2, k = radius / height
+        let k2 = (self.radius / self.height).powi(2);
+        let h = self.height - o.v[2];
+        let a = d.v[0]*d.v[0] + d.v[1]*d.v[1] - k2 * d.v[2]*d.v[2];
+        let b = 2. * (o.v[0]*d.v[0] + o.v[1]*d.v[1] + k2 * h * d.v[2]);
+        let c = o.v[0]*o.v[0] + o.v[1]*o.v[1] - k2 * h*h;
+        for t in solve_quadratic(a, b, c) {
+            if t < t_min || t > t_max { continue }
+            let p = o + d * t;
+            // Discard the mirrored cone above the apex
+            if p.v[2] < 0. || p.v[2] > self.height { continue }
+            let uv = point2::from(angle_uv(p.v[0], p.v[1]), p.v[2] / self.height);
+            let n = vec3::from(p.v[0], p.v[1], k2 * (self.height - p.v[2]));
+            local_hit::keep_closest(&mut best, t, n, uv);
+        }
+
+        if self.capped {
+            hit_cap(&o, &d, 0., self.radius, vec3::from(0., 0., -1.), t_min, t_max, &mut best);
+        }
+
+        match best {
+            Some(h) => { h.fill(r, &self.basis, &self.mat, rec); true },
+            None => false,
+        }
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        let apex = self.base + self.basis.w * self.height;
+        let pad = vec3::one() * FLAT_PAD;
+        (true, aabb::from_2_aabb(
+            disk_aabb(self.base, self.basis.w, self.radius),
+            aabb::from(apex - pad, apex + pad)))
+    }
+}
+
+//===================================================================
+
+/// Torus around axis, major radius to the center of the tube, minor radius of the tube
+/// uv: u around the axis, v around the tube
+pub struct torus {
+    center: point3,
+    basis: onb,
+    major: f64,
+    minor: f64,
+    mat: Arc<dyn Material>,
+}
+
+impl torus {
+    pub fn from(center: point3, axis: vec3, major: f64, minor: f64, mat: Arc<dyn Material>) -> torus {
+        torus { center, basis: onb::build_from_w(&axis), major, minor, mat }
+    }
+}
+
+unsafe impl Sync for torus{}
+unsafe impl Send for torus{}
+
+impl Hittable for torus {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        let mut o = self.basis.to_local(&(r.origin - self.center));
+        let d_len = r.dir.length();
+        let d = self.basis.to_local(&r.dir) / d_len;
+
+        // The quartic coefficients blow up with the distance to the torus,
+        // so start the ray at the bounding sphere when coming from far away
+        let shift = (o.length() - (self.major + self.minor) - 1.).max(0.);
+        o = o + d * shift;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4R^2 (x^2 + y^2), with |d| = 1
+        let r2 = self.major * self.major;
+        let e = o.length_squared() + r2 - self.minor * self.minor;
+        let f = o.dot(&d);
+        let dxy = d.v[0]*d.v[0] + d.v[1]*d.v[1];
+        let oxy = o.v[0]*d.v[0] + o.v[1]*d.v[1];
+        let oo = o.v[0]*o.v[0] + o.v[1]*o.v[1];
+
+        let roots = solve_quartic(
+            4. * f,
+            4. * f*f + 2. * e - 4. * r2 * dxy,
+            4. * e * f - 8. * r2 * oxy,
+            e*e - 4. * r2 * oo);
+
+        let t = match roots.into_iter().map(|s| (s + shift) / d_len).find(|t| *t >= t_min && *t <= t_max) {
+            Some(t) => t,
+            None => return false,
+        };
+
+        let p = self.basis.to_local(&(r.at(t) - self.center));
+        let xy = (p.v[0]*p.v[0] + p.v[1]*p.v[1]).sqrt();
+        // Normal is the direction from the closest point of the tube's center ring
+        let ring = vec3::from(p.v[0], p.v[1], 0.) * (self.major / xy);
+        let uv = point2::from(angle_uv(p.v[0], p.v[1]), angle_uv(xy - self.major, p.v[2]));
+
+        local_hit{t, n: p - ring, uv}.fill(r, &self.basis, &self.mat, rec);
+        true
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        let ring = disk_aabb(self.center, self.basis.w, self.major);
+        let pad = vec3::one() * self.minor;
+        (true, aabb::from(ring.min - pad, ring.max + pad))
+    }
+}
@@ -3,6 +3,40 @@ use std::sync::Arc;
 use crate::rtow_math::prelude::*;
 use crate::objects::prelude::*;
 
+// Area lights: the rects are sampled like quad, uniformly over their area. Their outward normal is
+// +z, +y and +x, a one sided emitter only lights that way
+
+/// Uniform point of the rect with corner p0 and edges e0, e1, as sample_surface() wants it
+fn rect_sample(p0: point3, e0: vec3, e1: vec3, n: vec3, mat: &Arc<dyn Material>) -> Option<(hit_record, f64)> {
+    let (alpha, beta) = (rand_f64(), rand_f64());
+    let mut rec = hit_record::new();
+    rec.p = p0 + e0 * alpha + e1 * beta;
+    rec.n = n;
+    rec.uv = point2::from(alpha, beta);
+    rec.set_tangents(e0, e1);
+    rec.mat = Arc::clone(mat);
+    Some((rec, 1. / (e0.length() * e1.length())))
+}
+
+/// Pdf by solid angle of rect.random() from origin along dir
+fn rect_pdf_value(rect: &dyn Hittable, origin: &point3, dir: &vec3) -> f64 {
+    let mut rec = hit_record::new();
+    if !rect.hit(&ray::from(*origin, *dir), 0.0001, INFINITY, &mut rec) { return 0. }
+
+    let dist_sq = rec.t * rec.t * dir.length_squared();
+    let cos = (dir.dot(&rec.n) / dir.length()).abs();
+    if cos < 1e-8 { return 0. }
+
+    dist_sq / (cos * rect.area())
+}
+
+/// Lambertian emitter, out of the outward face or both
+fn rect_light_bounds(rect: &dyn Hittable, n: vec3, mat: &Arc<dyn Material>) -> Option<light_bounds> {
+    let (lum, two_sided) = mat.light_emission()?;
+    let faces = if two_sided { 2. } else { 1. };
+    Some(light_bounds { bounds: rect.get_aabb(0., 1.).1, phi: lum * pi * rect.area() * faces, w: n, cos_theta_o: 1., cos_theta_e: 0., two_sided })
+}
+
 
 pub struct xy_rect {
     x0: f64,
@@ -42,6 +76,7 @@ impl Hittable for xy_rect {
 
         let out_normal = vec3::from(0.,0.,1.);
         rec.set_face_normal(r, out_normal);
+        rec.set_tangents(vec3::from(self.x1-self.x0, 0., 0.), vec3::from(0., self.y1-self.y0, 0.));
         rec.mat = Arc::clone(&self.mat);
         rec.p = r.at(t);
 
@@ -54,6 +89,20 @@ impl Hittable for xy_rect {
         )
     }
 
+    fn area(&self) -> f64 { (self.x1 - self.x0) * (self.y1 - self.y0) }
+
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 { rect_pdf_value(self, origin, dir) }
+
+    fn random(&self, origin: &point3) -> vec3 { point3::from(rand_f64_r(self.x0, self.x1), rand_f64_r(self.y0, self.y1), self.k) - *origin }
+
+    fn sample_surface(&self) -> Option<(hit_record, f64)> {
+        rect_sample(point3::from(self.x0, self.y0, self.k), vec3::from(self.x1 - self.x0, 0., 0.), vec3::from(0., self.y1 - self.y0, 0.), vec3::from(0., 0., 1.), &self.mat)
+    }
+
+    fn surface_pdf(&self, p: &point3) -> f64 { 1. / self.area() }
+
+    fn light_bounds(&self) -> Option<light_bounds> { rect_light_bounds(self, vec3::from(0., 0., 1.), &self.mat) }
+
 }
 
 //===================================================================
@@ -96,6 +145,7 @@ impl Hittable for xz_rect {
 
         let mut out_normal = vec3::from(0.,1.,0.);
         rec.set_face_normal(r, out_normal);
+        rec.set_tangents(vec3::from(self.x1-self.x0, 0., 0.), vec3::from(0., 0., self.z1-self.z0));
         rec.mat = Arc::clone(&self.mat);
         rec.p = r.at(t);
 
@@ -108,6 +158,20 @@ impl Hittable for xz_rect {
         )
     }
 
+    fn area(&self) -> f64 { (self.x1 - self.x0) * (self.z1 - self.z0) }
+
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 { rect_pdf_value(self, origin, dir) }
+
+    fn random(&self, origin: &point3) -> vec3 { point3::from(rand_f64_r(self.x0, self.x1), self.k, rand_f64_r(self.z0, self.z1)) - *origin }
+
+    fn sample_surface(&self) -> Option<(hit_record, f64)> {
+        rect_sample(point3::from(self.x0, self.k, self.z0), vec3::from(self.x1 - self.x0, 0., 0.), vec3::from(0., 0., self.z1 - self.z0), vec3::from(0., 1., 0.), &self.mat)
+    }
+
+    fn surface_pdf(&self, p: &point3) -> f64 { 1. / self.area() }
+
+    fn light_bounds(&self) -> Option<light_bounds> { rect_light_bounds(self, vec3::from(0., 1., 0.), &self.mat) }
+
 }
 
 //===================================================================
@@ -150,6 +214,7 @@ impl Hittable for yz_rect {
 
         let out_normal = vec3::from(1.,0.,0.);
         rec.set_face_normal(r, out_normal);
+        rec.set_tangents(vec3::from(0., self.y1-self.y0, 0.), vec3::from(0., 0., self.z1-self.z0));
         rec.mat = Arc::clone(&self.mat);
         rec.p = r.at(t);
 
@@ -162,4 +227,18 @@ impl Hittable for yz_rect {
         )
     }
 
+    fn area(&self) -> f64 { (self.y1 - self.y0) * (self.z1 - self.z0) }
+
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 { rect_pdf_value(self, origin, dir) }
+
+    fn random(&self, origin: &point3) -> vec3 { point3::from(self.k, rand_f64_r(self.y0, self.y1), rand_f64_r(self.z0, self.z1)) - *origin }
+
+    fn sample_surface(&self) -> Option<(hit_record, f64)> {
+        rect_sample(point3::from(self.k, self.y0, self.z0), vec3::from(0., self.y1 - self.y0, 0.), vec3::from(0., 0., self.z1 - self.z0), vec3::from(1., 0., 0.), &self.mat)
+    }
+
+    fn surface_pdf(&self, p: &point3) -> f64 { 1. / self.area() }
+
+    fn light_bounds(&self) -> Option<light_bounds> { rect_light_bounds(self, vec3::from(1., 0., 0.), &self.mat) }
+
 }
\ No newline at end of file
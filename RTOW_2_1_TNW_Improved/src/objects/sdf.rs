@@ -0,0 +1,385 @@
+use crate::materials::prelude::*;
+use std::sync::Arc;
+use crate::rtow_math::prelude::*;
+use crate::objects::prelude::*;
+
+// Signed distance fields: dist() is how far p is from the surface, negative inside.
+// sdf_object sphere traces them: from the current point we can always advance dist()
+// without crossing the surface, until dist() is small enough to call it a hit.
+// The primitives are all centered at the origin, use sdf_transform to place them.
+
+pub trait Sdf: Send + Sync {
+    fn dist(&self, p: &point3) -> f64;
+    /// Conservative box around the surface, the marching only happens inside of it
+    fn bounds(&self) -> aabb;
+}
+
+fn vec3_abs(p: &point3) -> vec3 { vec3::from(p.v[0].abs(), p.v[1].abs(), p.v[2].abs()) }
+fn vec3_max(p: &point3, m: f64) -> vec3 { vec3::from(p.v[0].max(m), p.v[1].max(m), p.v[2].max(m)) }
+
+fn mix(a: f64, b: f64, t: f64) -> f64 { a * (1. - t) + b * t }
+
+fn bounds_corners(b: &aabb) -> [point3; 8] {
+    let mut ret = [point3::new(); 8];
+    for i in 0..8 {
+        ret[i] = point3::from(
+            if i & 1 == 0 { b.min.v[0] } else { b.max.v[0] },
+            if i & 2 == 0 { b.min.v[1] } else { b.max.v[1] },
+            if i & 4 == 0 { b.min.v[2] } else { b.max.v[2] });
+    }
+    ret
+}
+
+//===================================================================
+// Hittable
+
+pub struct sdf_object {
+    sdf: Arc<dyn Sdf>,
+    bbox: aabb,
+    max_steps: i32,
+    epsilon: f64,
+    mat: Arc<dyn Material>,
+}
+
+impl sdf_object {
+    pub fn from(sdf: Arc<dyn Sdf>, mat: Arc<dyn Material>) -> sdf_object {
+        sdf_object::from_all(sdf, 256, 0.0001, mat)
+    }
+
+    /// epsilon is the distance considered a hit, scale it with the size of the object
+    pub fn from_all(sdf: Arc<dyn Sdf>, max_steps: i32, epsilon: f64, mat: Arc<dyn Material>) -> sdf_object {
+        let bbox = sdf.bounds();
+        sdf_object { sdf, bbox, max_steps, epsilon, mat }
+    }
+
+    /// Gradient of the field by central differences on a tetrahedron (4 samples instead of 6)
+    fn normal(&self, p: &point3) -> vec3 {
+        let h = self.epsilon * 0.5;
+        let ks = [vec3::from(1., -1., -1.), vec3::from(-1., -1., 1.), vec3::from(-1., 1., -1.), vec3::from(1., 1., 1.)];
+        let mut n = vec3::new();
+        for k in ks {
+            n = n + k * self.sdf.dist(&(*p + k * h));
+        }
+        n.unit_vec()
+    }
+}
+
+unsafe impl Sync for sdf_object{}
+unsafe impl Send for sdf_object{}
+
+impl Hittable for sdf_object {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        let (check, t0, t1) = self.bbox.hit_range(r, t_min, t_max);
+        if !check { return false }
+
+        // March with a normalized direction so distances are world units
+        let d_len = r.dir.length();
+        let d = r.dir / d_len;
+        let mut s = t0 * d_len;
+        let s_end = t1 * d_len;
+
+        // A ray that starts on the surface (the bounce we just did) must leave it first
+        let mut dist = self.sdf.dist(&(r.origin + d * s));
+        if dist.abs() < self.epsilon {
+            s += self.epsilon * 4.;
+            dist = self.sdf.dist(&(r.origin + d * s));
+        }
+        // From the inside (refraction) we march towards the exit, same thing with the sign flipped
+        let side = if dist < 0. { -1. } else { 1. };
+
+        let mut found = false;
+        for _ in 0..self.max_steps {
+            dist = side * self.sdf.dist(&(r.origin + d * s));
+            if dist < self.epsilon { found = true; break }
+            s += dist;
+            if s > s_end { return false }
+        }
+        if !found { return false }
+
+        let t = s / d_len;
+        if t < t_min || t > t_max { return false }
+
+        rec.t = t;
+        rec.p = r.at(t);
+        let n = self.normal(&rec.p);
+        rec.set_face_normal(r, n);
+        rec.mat = Arc::clone(&self.mat);
+        // No natural parametrization, spherical mapping of the normal
+        rec.uv.v[0] = (n.v[2].atan2(n.v[0]) + pi) / (2. * pi);
+        rec.uv.v[1] = n.v[1].clamp(-1., 1.).acos() / pi;
+
+        true
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        (true, self.bbox.clone())
+    }
+}
+
+//===================================================================
+// Primitives
+
+pub struct sdf_sphere {
+    radius: f64,
+}
+
+impl sdf_sphere {
+    pub fn from(radius: f64) -> sdf_sphere { sdf_sphere { radius } }
+}
+
+impl Sdf for sdf_sphere {
+    fn dist(&self, p: &point3) -> f64 { p.length() - self.radius }
+    fn bounds(&self) -> aabb { aabb::from(vec3::one() * -self.radius, vec3::one() * self.radius) }
+}
+
+/// Box of half_size with its edges rounded by radius (the rounding grows the box)
+pub struct round_box {
+    half_size: vec3,
+    radius: f64,
+}
+
+impl round_box {
+    pub fn from(half_size: vec3, radius: f64) -> round_box { round_box { half_size, radius } }
+}
+
+impl Sdf for round_box {
+    fn dist(&self, p: &point3) -> f64 {
+        let q = vec3_abs(p) - self.half_size;
+        vec3_max(&q, 0.).length() + q.v[0].max(q.v[1].max(q.v[2])).min(0.) - self.radius
+    }
+
+    fn bounds(&self) -> aabb {
+        let ext = self.half_size + self.radius;
+        aabb::from(ext * -1., ext)
+    }
+}
+
+/// Segment from a to b with a radius around it
+pub struct capsule {
+    a: point3,
+    b: point3,
+    radius: f64,
+}
+
+impl capsule {
+    pub fn from(a: point3, b: point3, radius: f64) -> capsule { capsule { a, b, radius } }
+}
+
+impl Sdf for capsule {
+    fn dist(&self, p: &point3) -> f64 {
+        let pa = *p - self.a;
+        let ba = self.b - self.a;
+        let h = (pa.dot(&ba) / ba.dot(&ba)).clamp(0., 1.);
+        (pa - ba * h).length() - self.radius
+    }
+
+    fn bounds(&self) -> aabb {
+        let mut ret = aabb::new();
+        for i in 0..3 {
+            ret.min.v[i] = self.a.v[i].min(self.b.v[i]) - self.radius;
+            ret.max.v[i] = self.a.v[i].max(self.b.v[i]) + self.radius;
+        }
+        ret
+    }
+}
+
+/// Torus around the Y axis
+pub struct sdf_torus {
+    major: f64,
+    minor: f64,
+}
+
+impl sdf_torus {
+    pub fn from(major: f64, minor: f64) -> sdf_torus { sdf_torus { major, minor } }
+}
+
+impl Sdf for sdf_torus {
+    fn dist(&self, p: &point3) -> f64 {
+        let xz = (p.v[0]*p.v[0] + p.v[2]*p.v[2]).sqrt() - self.major;
+        (xz*xz + p.v[1]*p.v[1]).sqrt() - self.minor
+    }
+
+    fn bounds(&self) -> aabb {
+        let r = self.major + self.minor;
+        aabb::from(point3::from(-r, -self.minor, -r), point3::from(r, self.minor, r))
+    }
+}
+
+/// Mandelbulb fractal of radius ~1, distance estimated from the running derivative
+/// More iterations add detail (and cost), power 8 is the classic bulb
+pub struct mandelbulb {
+    power: f64,
+    iterations: i32,
+}
+
+impl mandelbulb {
+    pub fn from(power: f64, iterations: i32) -> mandelbulb { mandelbulb { power, iterations } }
+}
+
+impl Sdf for mandelbulb {
+    fn dist(&self, p: &point3) -> f64 {
+        let mut z = *p;
+        let mut dr = 1.;
+        let mut r = z.length();
+
+        for _ in 0..self.iterations {
+            r = z.length();
+            if r > 2. { break }
+
+            // z = z^power + p, in spherical coordinates
+            let theta = (z.v[2] / r).clamp(-1., 1.).acos() * self.power;
+            let phi = z.v[1].atan2(z.v[0]) * self.power;
+            dr = r.powf(self.power - 1.) * self.power * dr + 1.;
+
+            let zr = r.powf(self.power);
+            z = vec3::from(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()) * zr + *p;
+        }
+
+        if r < 1e-12 { return 0. }
+        0.5 * r.ln() * r / dr
+    }
+
+    // Anything past the escape radius leaves on the first iteration, whatever the power
+    fn bounds(&self) -> aabb { aabb::from(vec3::one() * -2., vec3::one() * 2.) }
+}
+
+//===================================================================
+// Combinators
+
+/// Moves and uniformly scales another field
+pub struct sdf_transform {
+    inner: Arc<dyn Sdf>,
+    offset: vec3,
+    scale: f64,
+}
+
+impl sdf_transform {
+    pub fn from(inner: Arc<dyn Sdf>, offset: vec3, scale: f64) -> sdf_transform { sdf_transform { inner, offset, scale } }
+}
+
+impl Sdf for sdf_transform {
+    fn dist(&self, p: &point3) -> f64 {
+        self.inner.dist(&((*p - self.offset) / self.scale)) * self.scale
+    }
+
+    fn bounds(&self) -> aabb {
+        let b = self.inner.bounds();
+        aabb::from(b.min * self.scale + self.offset, b.max * self.scale + self.offset)
+    }
+}
+
+/// Union that blends the surfaces closer than k, k = 0 is a plain union
+pub struct smooth_union {
+    a: Arc<dyn Sdf>,
+    b: Arc<dyn Sdf>,
+    k: f64,
+}
+
+impl smooth_union {
+    pub fn from(a: Arc<dyn Sdf>, b: Arc<dyn Sdf>, k: f64) -> smooth_union { smooth_union { a, b, k } }
+}
+
+impl Sdf for smooth_union {
+    fn dist(&self, p: &point3) -> f64 {
+        let (d1, d2) = (self.a.dist(p), self.b.dist(p));
+        if self.k <= 0. { return d1.min(d2) }
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0., 1.);
+        mix(d2, d1, h) - self.k * h * (1. - h)
+    }
+
+    fn bounds(&self) -> aabb {
+        // The blend takes up to k / 4 off the distance, the surface bulges that far past both
+        let b = aabb::from_2_aabb(self.a.bounds(), self.b.bounds());
+        let pad = vec3::one() * (self.k.max(0.) / 4.);
+        aabb::from(b.min - pad, b.max + pad)
+    }
+}
+
+/// a with b carved out of it, smoothed by k like smooth_union
+pub struct subtraction {
+    a: Arc<dyn Sdf>,
+    b: Arc<dyn Sdf>,
+    k: f64,
+}
+
+impl subtraction {
+    pub fn from(a: Arc<dyn Sdf>, b: Arc<dyn Sdf>, k: f64) -> subtraction { subtraction { a, b, k } }
+}
+
+impl Sdf for subtraction {
+    fn dist(&self, p: &point3) -> f64 {
+        let (d1, d2) = (self.a.dist(p), self.b.dist(p));
+        if self.k <= 0. { return d1.max(-d2) }
+        let h = (0.5 - 0.5 * (d1 + d2) / self.k).clamp(0., 1.);
+        mix(d1, -d2, h) + self.k * h * (1. - h)
+    }
+
+    fn bounds(&self) -> aabb { self.a.bounds() }
+}
+
+/// Copies of the field every period, count copies to each side of the origin per axis
+/// (count 0 on an axis doesn't repeat along it). The field should fit in a period.
+pub struct repetition {
+    inner: Arc<dyn Sdf>,
+    period: vec3,
+    count: [i32; 3],
+}
+
+impl repetition {
+    pub fn from(inner: Arc<dyn Sdf>, period: vec3, count: [i32; 3]) -> repetition { repetition { inner, period, count } }
+}
+
+impl Sdf for repetition {
+    fn dist(&self, p: &point3) -> f64 {
+        let mut q = *p;
+        for i in 0..3 {
+            if self.count[i] == 0 { continue }
+            let c = self.count[i] as f64;
+            q.v[i] = p.v[i] - self.period.v[i] * (p.v[i] / self.period.v[i]).round().clamp(-c, c);
+        }
+        self.inner.dist(&q)
+    }
+
+    fn bounds(&self) -> aabb {
+        let b = self.inner.bounds();
+        let mut ext = vec3::new();
+        for i in 0..3 { ext.v[i] = self.period.v[i].abs() * self.count[i] as f64 }
+        aabb::from(b.min - ext, b.max + ext)
+    }
+}
+
+/// Twist around the Y axis, turning amount radians per unit of height
+pub struct twist {
+    inner: Arc<dyn Sdf>,
+    amount: f64,
+    radius: f64,
+    lipschitz: f64,
+}
+
+impl twist {
+    pub fn from(inner: Arc<dyn Sdf>, amount: f64) -> twist {
+        // Farthest the field reaches from the axis, bounds the twisted box
+        let radius = bounds_corners(&inner.bounds()).iter()
+            .map(|c| (c.v[0]*c.v[0] + c.v[2]*c.v[2]).sqrt())
+            .fold(0., f64::max);
+        // Twisting stretches distances up to this much, divide by it to stay conservative
+        let lipschitz = (1. + (amount * radius).powi(2)).sqrt();
+        twist { inner, amount, radius, lipschitz }
+    }
+}
+
+impl Sdf for twist {
+    fn dist(&self, p: &point3) -> f64 {
+        let angle = self.amount * p.v[1];
+        let (s, c) = angle.sin_cos();
+        let q = point3::from(c * p.v[0] - s * p.v[2], p.v[1], s * p.v[0] + c * p.v[2]);
+        self.inner.dist(&q) / self.lipschitz
+    }
+
+    fn bounds(&self) -> aabb {
+        let b = self.inner.bounds();
+        aabb::from(
+            point3::from(-self.radius, b.min.v[1], -self.radius),
+            point3::from(self.radius, b.max.v[1], self.radius))
+    }
+}
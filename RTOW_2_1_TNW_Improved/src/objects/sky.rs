@@ -0,0 +1,224 @@
+use crate::materials::prelude::*;
+use crate::rtow_math::prelude::*;
+use crate::objects::prelude::*;
+
+// Daylight without HDR files: the Preetham, Shirley and Smits (1999) analytic sky, a sun disk
+// dimmed by the same atmosphere and a flat ground below the horizon.
+// Preetham gives luminance in kcd/m2, KCD_TO_RADIANCE brings it to the scale of the renderer:
+// a white diffuse surface under a high sun comes out around 1.
+
+const KCD_TO_RADIANCE: f64 = 1. / 40.;
+// Luminance of the sun before going through the atmosphere, kcd/m2
+const SUN_LUMINANCE: f64 = 2.0e6;
+// Angular radius of the sun seen from the earth, radians
+pub const SUN_ANGULAR_RADIUS: f64 = 0.00465;
+
+/// Perez et al. luminance distribution, relative to the zenith through zenith()
+#[derive(Debug, Copy, Clone)]
+struct perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl perez {
+    /// theta from the zenith to the view direction, gamma between the view and the sun
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let cos_gamma = gamma.cos();
+        (1. + self.a * (self.b / cos_theta.max(0.001)).exp()) * (1. + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+/// Preetham sky and sun, as a Background for the rays that miss everything
+/// sun_light() and sky_light() go in the lights list of light_sampling so both get sampled directly
+pub struct physical_sky {
+    sun_dir: vec3,
+    turbidity: f64,
+    ground_albedo: colorRGB,
+    pub intensity: f64,
+    // Preetham for Y, x and y, and their values at the zenith
+    perez_y: perez,
+    perez_cx: perez,
+    perez_cy: perez,
+    zenith: vec3,
+    sun_radiance: colorRGB,
+    ground: colorRGB,
+}
+
+impl physical_sky {
+    /// sun_dir towards the sun, y is up. Turbidity goes from 2 (very clear) to 10 (hazy),
+    /// ground_albedo is the colour of what is below the horizon
+    pub fn from(sun_dir: vec3, turbidity: f64, ground_albedo: colorRGB) -> physical_sky {
+        let sun_dir = sun_dir.unit_vec();
+        // The fit is only valid in this range, and breaks down once the sun sets
+        let t = turbidity.clamp(1.7, 10.);
+        let theta_s = sun_dir.v[1].clamp(0., 1.).acos();
+
+        let perez_y = perez { a: 0.1787 * t - 1.4630, b: -0.3554 * t + 0.4275, c: -0.0227 * t + 5.3251, d: 0.1206 * t - 2.5771, e: -0.0670 * t + 0.3703 };
+        let perez_cx = perez { a: -0.0193 * t - 0.2592, b: -0.0665 * t + 0.0008, c: -0.0004 * t + 0.2125, d: -0.0641 * t - 0.8989, e: -0.0033 * t + 0.0452 };
+        let perez_cy = perez { a: -0.0167 * t - 0.2608, b: -0.0950 * t + 0.0092, c: -0.0079 * t + 0.2102, d: -0.0441 * t - 1.6537, e: -0.0109 * t + 0.0529 };
+
+        let chi = (4. / 9. - t / 120.) * (pi - 2. * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (th, th2, th3) = (theta_s, theta_s * theta_s, theta_s * theta_s * theta_s);
+        let zenith_x = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_cy = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        let mut sky = physical_sky {
+            sun_dir, turbidity: t, ground_albedo,
+            intensity: 1.,
+            perez_y, perez_cx, perez_cy,
+            zenith: vec3::from(zenith_y, zenith_x, zenith_cy),
+            sun_radiance: colorRGB::new(),
+            ground: colorRGB::new(),
+        };
+        sky.sun_radiance = if sun_dir.v[1] > 0. { sky.sun_transmittance() * (SUN_LUMINANCE * KCD_TO_RADIANCE) } else { colorRGB::new() };
+        sky.ground = ground_albedo * (sky.horizontal_irradiance() / pi);
+        sky
+    }
+
+    /// Sun at elevation degrees over the horizon, azimuth degrees around y from +x towards +z
+    pub fn from_angles(elevation: f64, azimuth: f64, turbidity: f64, ground_albedo: colorRGB) -> physical_sky {
+        let (el, az) = (deg_to_rad(elevation), deg_to_rad(azimuth));
+        physical_sky::from(vec3::from(el.cos() * az.cos(), el.sin(), el.cos() * az.sin()), turbidity, ground_albedo)
+    }
+
+    pub fn sun_dir(&self) -> vec3 {
+        self.sun_dir
+    }
+
+    /// Rayleigh and aerosol (Angstrom) extinction along the path of the sunlight through the air,
+    /// at a representative wavelength of each channel. Gives the white sun at noon and the red one at dusk
+    fn sun_transmittance(&self) -> colorRGB {
+        let theta_s = self.sun_dir.v[1].clamp(0., 1.).acos();
+        // Relative optical mass, Kasten's fit that stays finite at the horizon
+        let m = 1. / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let lambdas: [f64; 3] = [0.65, 0.55, 0.45];
+        let white = blackbody(5778.);
+        let mut ret = colorRGB::new();
+        for i in 0..3 {
+            let rayleigh = (-0.008735 * lambdas[i].powf(-4.08) * m).exp();
+            let aerosol = (-beta * lambdas[i].powf(-1.3) * m).exp();
+            ret.v[i] = white.v[i] * rayleigh * aerosol;
+        }
+        ret
+    }
+
+    /// Sky alone, dir above the horizon and normalized
+    fn sky_radiance(&self, dir: &vec3) -> colorRGB {
+        let cos_theta = dir.v[1].max(0.);
+        let cos_theta_s = self.sun_dir.v[1].clamp(0., 1.);
+        let gamma = dir.dot(&self.sun_dir).clamp(-1., 1.).acos();
+        let theta_s = cos_theta_s.acos();
+
+        let relative = |p: &perez| p.f(cos_theta, gamma) / p.f(1., theta_s);
+        let lum = self.zenith.v[0] * relative(&self.perez_y);
+        let x = self.zenith.v[1] * relative(&self.perez_cx);
+        let y = self.zenith.v[2] * relative(&self.perez_cy);
+        if y <= 0. || lum <= 0. { return colorRGB::new() }
+
+        // xyY to XYZ to rgb
+        let rgb = xyz_to_srgb(&vec3::from(x / y * lum, lum, (1. - x - y) / y * lum));
+        colorRGB::from(rgb.v[0].max(0.), rgb.v[1].max(0.), rgb.v[2].max(0.)) * KCD_TO_RADIANCE
+    }
+
+    /// Light reaching the ground from the sun and the whole sky, numerically over the hemisphere
+    fn horizontal_irradiance(&self) -> colorRGB {
+        let (n_theta, n_phi) = (32, 64);
+        let (d_theta, d_phi) = (0.5 * pi / n_theta as f64, 2. * pi / n_phi as f64);
+        let mut ret = colorRGB::new();
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let dir = vec3::from(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin());
+                ret = ret + self.sky_radiance(&dir) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+        let sun_solid_angle = 2. * pi * (1. - SUN_ANGULAR_RADIUS.cos());
+        ret + self.sun_radiance * (sun_solid_angle * self.sun_dir.v[1].max(0.))
+    }
+
+    /// Sky and ground without the sun disk, what sky_light() samples
+    fn value_no_sun(&self, dir: &vec3) -> colorRGB {
+        let dir = dir.unit_vec();
+        let ret = if dir.v[1] > 0. { self.sky_radiance(&dir) } else { self.ground };
+        ret * self.intensity
+    }
+
+    /// The sun disk, to go in the lights list
+    pub fn sun_light(&self) -> sun_disk {
+        sun_disk::from(self.sun_dir, SUN_ANGULAR_RADIUS)
+    }
+
+    /// The sky dome as an environment_light of width x width / 2 texels, to go in the lights list
+    /// to sample the sky by its brightness. Only used for the sampling, the sky itself stays analytic
+    pub fn sky_light(&self, width: usize) -> environment_light {
+        let height = (width / 2).max(1);
+        let mut data = vec![0f32; width * height * 3];
+        for j in 0..height {
+            for i in 0..width {
+                // Same mapping as environment_light, row 0 is straight up
+                let (u, v) = ((i as f64 + 0.5) / width as f64, 1. - (j as f64 + 0.5) / height as f64);
+                let (theta, phi) = (v * pi, u * 2. * pi);
+                let dir = vec3::from(-theta.sin() * phi.cos(), -theta.cos(), theta.sin() * phi.sin());
+                let c = self.value_no_sun(&dir);
+                for k in 0..3 { data[(i + j * width) * 3 + k] = c.v[k] as f32 }
+            }
+        }
+        environment_light::from_image(RTOW_Image::from_data(width, height, 3, data), 1., 0.)
+    }
+}
+
+impl Background for physical_sky {
+    fn value(&self, dir: &vec3) -> colorRGB {
+        let unit = dir.unit_vec();
+        if unit.dot(&self.sun_dir) >= SUN_ANGULAR_RADIUS.cos() && self.sun_dir.v[1] > 0. {
+            return self.sun_radiance * self.intensity
+        }
+        self.value_no_sun(&unit)
+    }
+}
+
+/// Light from a small cone of directions, infinitely far (the sun). Only a Hittable to go in the
+/// lights list, its radiance comes from the Background
+pub struct sun_disk {
+    dir: vec3,
+    cos_max: f64,
+}
+
+impl sun_disk {
+    pub fn from(dir: vec3, angular_radius: f64) -> sun_disk {
+        sun_disk { dir: dir.unit_vec(), cos_max: angular_radius.cos() }
+    }
+}
+
+impl Hittable for sun_disk {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        false
+    }
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        (false, aabb::new())
+    }
+
+    // Uniform over the cone
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 {
+        if dir.unit_vec().dot(&self.dir) < self.cos_max { return 0. }
+        1. / (2. * pi * (1. - self.cos_max))
+    }
+
+    fn random(&self, origin: &point3) -> vec3 {
+        let cos_theta = 1. - rand_f64() * (1. - self.cos_max);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * pi * rand_f64();
+        onb::build_from_w(&self.dir).local(&vec3::from(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+    }
+}
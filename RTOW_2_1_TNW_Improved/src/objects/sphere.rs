@@ -5,6 +5,8 @@ use crate::materials::Default;
 use crate::objects::hit::*;
 use crate::objects::aabb::*;
 use crate::rtow_math::vec2::*;
+use crate::rtow_math::onb::*;
+use crate::objects::light_sampler::*;
 
 /// Knowing that a point is outside a sphere if x^2 + y^2 + z^2 > Radius^2
 /// Assuming sphere is at origin, else we just transalate point by center and calculate again
@@ -70,27 +72,39 @@ impl sphere {
     }
 
     fn get_uv(&self, hit_pos: &point3, uv: &mut point2) {
-        // p: a given point on the sphere of radius one, centered at the origin.
-        // u: returned value [0,1] of angle around the Y axis from X=-1.
-        // v: returned value [0,1] of angle from Y=-1 to Y=+1.
-        //     <1 0 0> yields <0.50 0.50>       <-1  0  0> yields <0.00 0.50>
-        //     <0 1 0> yields <0.50 1.00>       < 0 -1  0> yields <0.50 0.00>
-        //     <0 0 1> yields <0.25 0.50>       < 0  0 -1> yields <0.75 0.50>
-        
-        // Because rust acos onyl works in range {-1,1}, move hit pos to relative origin
-        // Then set it to range {-1,1} using the radius
-        let frac_y = (hit_pos.v[1] - self.center.v[1]) / self.radius;
-        let frac_hit = (*hit_pos - self.center) / self.radius;
-        let adj_y = frac_y * (hit_pos.v[1] / hit_pos.v[1].abs());
-
-        let theta = (-frac_hit.v[1]).acos();        
-        let phi = (-frac_hit.v[2]).atan2(frac_hit.v[0]) + PI;
-        
-        uv.v[0] = phi / (2.*PI);
-        uv.v[1] = theta / PI;
+        *uv = sphere_uv(&((*hit_pos - self.center) / self.radius));
+    }
+
+    fn get_tangents(&self, hit_pos: &point3) -> (vec3, vec3) {
+        sphere_tangents(&((*hit_pos - self.center) / self.radius), self.radius)
     }
 }
 
+/// uv of o, a point on the sphere of radius one centered at the origin
+/// u: [0,1] of angle around the Y axis from X=-1.
+/// v: [0,1] of angle from Y=-1 to Y=+1.
+///     <1 0 0> yields <0.50 0.50>       <-1  0  0> yields <0.00 0.50>
+///     <0 1 0> yields <0.50 1.00>       < 0 -1  0> yields <0.50 0.00>
+///     <0 0 1> yields <0.25 0.50>       < 0  0 -1> yields <0.75 0.50>
+pub fn sphere_uv(o: &vec3) -> point2 {
+    // Rust acos only works in [-1,1], o can be a bit outside after the division by the radius
+    let theta = (-o.v[1]).clamp(-1., 1.).acos();
+    let phi = (-o.v[2]).atan2(o.v[0]) + PI;
+    point2::from(phi / (2.*PI), theta / PI)
+}
+
+/// Derivatives of the sphere_uv parametrisation for a sphere of radius radius, o as in sphere_uv
+/// p = center + radius * (-sin(theta)cos(phi), -cos(theta), sin(theta)sin(phi))
+pub fn sphere_tangents(o: &vec3, radius: f64) -> (vec3, vec3) {
+    let sin_theta = (1. - o.v[1] * o.v[1]).max(0.).sqrt();
+    // dp/dv is undefined at the poles
+    if sin_theta < 1e-8 { return (vec3::new(), vec3::new()) }
+
+    let dpdu = vec3::from(o.v[2], 0., -o.v[0]) * (2. * PI * radius);
+    let dpdv = vec3::from(-o.v[0] * o.v[1] / sin_theta, sin_theta, -o.v[1] * o.v[2] / sin_theta) * (PI * radius);
+    (dpdu, dpdv)
+}
+
 use crate::objects::hittable_list::*;
 use std::sync::Mutex;
 
@@ -123,6 +137,8 @@ impl Hittable for sphere {
         rec.set_face_normal(r, rec.n);
         rec.mat = Arc::clone(&self.mat);
         self.get_uv(&rec.p, &mut rec.uv);
+        let (dpdu, dpdv) = self.get_tangents(&rec.p);
+        rec.set_tangents(dpdu, dpdv);
 
         true
     }   
@@ -133,6 +149,71 @@ impl Hittable for sphere {
             self.center + vec3::from(self.radius, self.radius, self.radius)
         ))
     }
+
+    // Sphere light: uniform in the cone of directions it covers seen from outside, uniform on its
+    // surface from inside
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 {
+        let mut rec = hit_record::new();
+        if !self.hit(&ray::from(*origin, *dir), 0.0001, f64::INFINITY, &mut rec) { return 0. }
+
+        let (dist2, r2) = ((self.center - *origin).length_squared(), self.radius * self.radius);
+        if dist2 > r2 { return 1. / (2. * PI * one_minus_cos_max(r2, dist2)) }
+
+        let cos = (dir.dot(&rec.n) / dir.length()).abs();
+        if cos < 1e-8 { return 0. }
+        rec.t * rec.t * dir.length_squared() / (cos * 4. * PI * r2)
+    }
+
+    fn random(&self, origin: &point3) -> vec3 {
+        let to_center = self.center - *origin;
+        let (dist2, r2) = (to_center.length_squared(), self.radius * self.radius);
+        if dist2 <= r2 { return self.center + random_unit_vector() * self.radius - *origin }
+
+        let cos_theta = 1. - rand_f64() * one_minus_cos_max(r2, dist2);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * rand_f64();
+        onb::build_from_w(&to_center).local(&vec3::from(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta))
+    }
+
+    fn sample_surface(&self) -> Option<(hit_record, f64)> {
+        let o = random_unit_vector();
+        let mut rec = hit_record::new();
+        rec.p = self.center + o * self.radius;
+        rec.n = o;
+        rec.uv = sphere_uv(&o);
+        let (dpdu, dpdv) = sphere_tangents(&o, self.radius);
+        rec.set_tangents(dpdu, dpdv);
+        rec.mat = Arc::clone(&self.mat);
+        let pdf = self.surface_pdf(&rec.p);
+        Some((rec, pdf))
+    }
+
+    fn surface_pdf(&self, p: &point3) -> f64 {
+        1. / self.area()
+    }
+
+    fn area(&self) -> f64 {
+        4. * PI * self.radius * self.radius
+    }
+
+    fn light_bounds(&self) -> Option<light_bounds> {
+        let (lum, _) = self.mat.light_emission()?;
+        Some(light_bounds {
+            bounds: self.get_aabb(0., 1.).1,
+            phi: lum * PI * 4. * PI * self.radius * self.radius,
+            w: vec3::up(),
+            cos_theta_o: -1.,
+            cos_theta_e: 0.,
+            two_sided: false,
+        })
+    }
+}
+
+/// 1 - cos of the half angle a sphere of radius^2 r2 covers from dist^2 dist2 away, without
+/// losing it all to rounding for small far spheres
+fn one_minus_cos_max(r2: f64, dist2: f64) -> f64 {
+    let sin2 = r2 / dist2;
+    sin2 / (1. + (1. - sin2).sqrt())
 }
 
 use crate::rtow_math::defines;
@@ -164,6 +245,13 @@ pub fn random_in_sphere() -> point3 {
     point3::new() // Something has gone terribly bad
 }
 
+pub fn random_unit_vector() -> vec3 {
+    let z = rand_f64_r(-1., 1.);
+    let theta = 2. * PI * rand_f64_r(0., 1.);
+    let r = (1. - z * z).sqrt();
+    vec3::from(r * theta.cos(), r * theta.sin(), z)
+}
+
 pub fn random_in_unit_cube() -> point3 {
     point3::from(
         rand_f64_r(-0.5, 0.5),
@@ -237,8 +325,13 @@ impl Hittable for moving_sphere {
         rec.n = (rec.p - self.center(r.time)) / self.radius; // This is bad, only returns normal pointing outwards
         rec.iters += 1;
         // What if we need to differentiate between from and back face!
+        let o = rec.n;
         rec.set_face_normal(r, rec.n);
         rec.mat = Arc::clone(&self.mat);
+        // Same mapping as sphere, the texture moves along with the center
+        rec.uv = sphere_uv(&o);
+        let (dpdu, dpdv) = sphere_tangents(&o, self.radius);
+        rec.set_tangents(dpdu, dpdv);
 
         true
     }   
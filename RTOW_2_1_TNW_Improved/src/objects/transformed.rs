@@ -22,7 +22,9 @@ impl Hittable for translated {
         };
 
         rec.p = rec.p + self.offset;
+        let (dpdu, dpdv) = (rec.tangent, rec.bitangent);
         rec.set_face_normal(&mov_r, rec.n);
+        rec.set_tangents(dpdu, dpdv);
         true
 
     }
@@ -34,6 +36,33 @@ impl Hittable for translated {
             aabb::from(ret_aabb.min + self.offset, ret_aabb.max + self.offset)
         )
     }
+
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 {
+        self.obj.pdf_value(&(*origin - self.offset), dir)
+    }
+
+    fn random(&self, origin: &point3) -> vec3 {
+        self.obj.random(&(*origin - self.offset))
+    }
+
+    fn light_bounds(&self) -> Option<light_bounds> {
+        let lb = self.obj.light_bounds()?;
+        Some(light_bounds { bounds: aabb::from(lb.bounds.min + self.offset, lb.bounds.max + self.offset), ..lb })
+    }
+
+    fn sample_surface(&self) -> Option<(hit_record, f64)> {
+        let (mut rec, pdf) = self.obj.sample_surface()?;
+        rec.p = rec.p + self.offset;
+        Some((rec, pdf))
+    }
+
+    fn surface_pdf(&self, p: &point3) -> f64 {
+        self.obj.surface_pdf(&(*p - self.offset))
+    }
+
+    fn area(&self) -> f64 {
+        self.obj.area()
+    }
 }
 
 pub struct rotated {
@@ -83,6 +112,21 @@ impl rotated {
 
         self.bbox = aabb::from(v_min, v_max);
     }
+
+    // Same rotations as in hit(), for single vectors
+    fn rot_to_local(&self, v: &vec3) -> vec3 {
+        vec3::from(
+            -self.theta_vals.v[1] * v.v[2] + self.theta_vals.v[0] * v.v[0],
+            v.v[1],
+            self.theta_vals.v[0] * v.v[2] + self.theta_vals.v[1] * v.v[0])
+    }
+
+    fn rot_to_world(&self, v: &vec3) -> vec3 {
+        vec3::from(
+            self.theta_vals.v[0] * v.v[0] + self.theta_vals.v[1] * v.v[2],
+            v.v[1],
+            -self.theta_vals.v[1] * v.v[0] + self.theta_vals.v[0] * v.v[2])
+    }
 }
 
 impl Hittable for rotated {
@@ -118,7 +162,9 @@ impl Hittable for rotated {
         n.v[2] = -self.theta_vals.v[1] * rec.n.v[0] + self.theta_vals.v[0] * rec.n.v[2]; // -sin*x + cos*z
 
         rec.p = p;
+        let (dpdu, dpdv) = (self.rot_to_world(&rec.tangent), self.rot_to_world(&rec.bitangent));
         rec.set_face_normal(&rotated_r, n);
+        rec.set_tangents(dpdu, dpdv);
 
         true
         
@@ -128,4 +174,35 @@ impl Hittable for rotated {
         (self.hasbox, self.bbox.clone())
     }
 
+    fn pdf_value(&self, origin: &point3, dir: &vec3) -> f64 {
+        self.obj.pdf_value(&self.rot_to_local(origin), &self.rot_to_local(dir))
+    }
+
+    fn random(&self, origin: &point3) -> vec3 {
+        self.rot_to_world(&self.obj.random(&self.rot_to_local(origin)))
+    }
+
+    fn light_bounds(&self) -> Option<light_bounds> {
+        if !self.hasbox { return None }
+        let lb = self.obj.light_bounds()?;
+        Some(light_bounds { bounds: self.bbox.clone(), w: self.rot_to_world(&lb.w), ..lb })
+    }
+
+    fn sample_surface(&self) -> Option<(hit_record, f64)> {
+        let (mut rec, pdf) = self.obj.sample_surface()?;
+        rec.p = self.rot_to_world(&rec.p);
+        rec.n = self.rot_to_world(&rec.n);
+        rec.tangent = self.rot_to_world(&rec.tangent);
+        rec.bitangent = self.rot_to_world(&rec.bitangent);
+        Some((rec, pdf))
+    }
+
+    fn surface_pdf(&self, p: &point3) -> f64 {
+        self.obj.surface_pdf(&self.rot_to_local(p))
+    }
+
+    fn area(&self) -> f64 {
+        self.obj.area()
+    }
+
 }
\ No newline at end of file
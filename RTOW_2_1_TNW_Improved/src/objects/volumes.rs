@@ -3,6 +3,26 @@ use crate::objects::prelude::*;
 use crate::materials::prelude::*;
 use std::sync::Arc;
 
+/// Entry/exit t pairs of the ray inside the boundary, clamped to [t_min, t_max]
+/// A ray can go in and out many times (torus, hollow shapes...), the ray is assumed to start outside
+fn boundary_intervals(boundary: &dyn Hittable, r: &ray, t_min: f64, t_max: f64) -> Vec<(f64, f64)> {
+    let mut crossings = Vec::new();
+    let mut rec = hit_record::new();
+    let mut t = -INFINITY;
+
+    // Default check, if t_min were to be inside volume, we don't hit
+    // But volumes have constant hit so we must check along all the ray
+    while crossings.len() < 64 && boundary.hit(r, t, INFINITY, &mut rec) {
+        crossings.push(rec.t);
+        t = rec.t + 0.0001;
+    }
+
+    crossings.chunks_exact(2).filter_map(|c| {
+        let (t0, t1) = (c[0].max(t_min).max(0.), c[1].min(t_max));
+        if t0 < t1 { Some((t0, t1)) } else { None }
+    }).collect()
+}
+
 pub struct constant_medium {
     boundary: Box<dyn Hittable>,
     phase_function: Arc<dyn Material>,
@@ -16,6 +36,11 @@ impl constant_medium {
 
         constant_medium { boundary, phase_function, neg_inv_density }
     }
+
+    pub fn with_phase(boundary: Box<dyn Hittable>, density: f64, tex: Arc<dyn Texture>, phase: Arc<dyn PhaseFunction>) -> constant_medium {
+        let phase_function = Arc::new(phase_material::from(phase, tex));
+        constant_medium { boundary, phase_function, neg_inv_density: -1. / density }
+    }
 }
 
 unsafe impl Sync for constant_medium {}
@@ -23,47 +48,203 @@ unsafe impl Send for constant_medium {}
 
 impl Hittable for constant_medium {
     fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
-        let (mut rec1, mut rec2) = (hit_record::new(), hit_record::new());
+        let r_len  = r.dir.length();
+        let mut hit_dist = self.neg_inv_density * rand_f64().ln();
+
+        // The distance is spent across all the pieces of the volume the ray goes through
+        for (t0, t1) in boundary_intervals(self.boundary.as_ref(), r, t_min, t_max) {
+            let dist_in_boundary = (t1 - t0) * r_len;
+            if hit_dist > dist_in_boundary {
+                hit_dist -= dist_in_boundary;
+                continue;
+            }
+
+            rec.t = t0 + hit_dist / r_len;
+            rec.p = r.at(rec.t);
+
+            rec.n = vec3::up();
+            rec.front_face = true;
+            rec.mat = Arc::clone(&self.phase_function);
+            // No surface inside a medium, rec may still hold the uv of an earlier hit
+            rec.uv = point2::new();
+            rec.set_tangents(vec3::new(), vec3::new());
+
+            return true
+        }
+
+        false
+    }
+
+
+    fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
+        self.boundary.get_aabb(time0, time1)
+    }
+}
+
+/// Density that changes inside a medium
+pub trait Density: Send + Sync {
+    fn density(&self, p: &point3) -> f64;
+    /// Upper bound of density() everywhere, the majorant for delta tracking
+    fn majorant(&self) -> f64;
+}
+
+/// nx * ny * nz densities spread over bbox, trilinear between them and 0 outside
+pub struct density_grid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    values: Vec<f64>,
+    bbox: aabb,
+    max: f64,
+}
+
+impl density_grid {
+    /// values go along x, then y, then z: values[i + j * nx + k * nx * ny]
+    pub fn from(nx: usize, ny: usize, nz: usize, values: Vec<f64>, bbox: aabb) -> density_grid {
+        if nx < 2 || ny < 2 || nz < 2 || values.len() != nx * ny * nz { panic!("Density grid needs at least 2x2x2 values and nx * ny * nz of them") };
+        let max = values.iter().cloned().fold(0., f64::max);
+        density_grid { nx, ny, nz, values, bbox, max }
+    }
+
+    /// Fills the grid evaluating f at each of its nx * ny * nz points, the first and last on each axis
+    /// on the faces of bbox like the values density() interpolates between
+    pub fn from_fn(nx: usize, ny: usize, nz: usize, bbox: aabb, f: impl Fn(&point3) -> f64) -> density_grid {
+        if nx < 2 || ny < 2 || nz < 2 { panic!("Density grid needs at least 2x2x2 values and nx * ny * nz of them") };
+        let size = bbox.max - bbox.min;
+        let mut values = Vec::with_capacity(nx * ny * nz);
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    let t = vec3::from(i as f64 / (nx - 1) as f64, j as f64 / (ny - 1) as f64, k as f64 / (nz - 1) as f64);
+                    values.push(f(&(bbox.min + size * t)).max(0.));
+                }
+            }
+        }
+        density_grid::from(nx, ny, nz, values, bbox)
+    }
+
+    fn value(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.values[i + j * self.nx + k * self.nx * self.ny]
+    }
+}
 
-        // Default check, if t_min were to be inside volume, we don't hit 
-        // But volumes have constant hit so we must check along all the ray
-        if(!self.boundary.hit(r, -INFINITY, INFINITY, &mut rec1)) {
-            return false
+impl Density for density_grid {
+    fn density(&self, p: &point3) -> f64 {
+        let dims = [self.nx, self.ny, self.nz];
+        let mut cell = [0 as usize; 3];
+        let mut t = [0.; 3];
+        for a in 0..3 {
+            let rel = (p.v[a] - self.bbox.min.v[a]) / (self.bbox.max.v[a] - self.bbox.min.v[a]);
+            if !(0. ..=1.).contains(&rel) { return 0. }
+            let f = rel * (dims[a] - 1) as f64;
+            cell[a] = (f.floor() as usize).min(dims[a] - 2);
+            t[a] = f - cell[a] as f64;
         }
 
-        // Here we actually check at which point we are hitting
-        // aka, how much of the volume we have traversed or not
-        if(!self.boundary.hit(r, rec1.t+0.0001, INFINITY, &mut rec2)) {
-            return false
+        let mut corners = [0.; 2*2*2];
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    corners[i*2*2 + j*2 + k] = self.value(cell[0] + i, cell[1] + j, cell[2] + k);
+                }
+            }
         }
+        f64_trilerp(corners, t)
+    }
 
-        // However, we are assuming that from the inside, we only escape once
-        // Does not check for multiple hits like in a torus or other shapes that have voids
-        // or holes in them!
+    fn majorant(&self) -> f64 { self.max }
+}
 
-        if rec1.t < t_min {rec1.t = t_min};
-        if rec2.t > t_max {rec2.t = t_max};
+/// Perlin turbulence as density, cutoff in [0,1) removes the thin parts to get separated puffs
+pub struct noise_density {
+    noise: Perlin_Noise,
+    density: f64,
+    scale: f64,
+    depth: i32,
+    cutoff: f64,
+}
 
-        if rec1.t < 0. {rec1.t = 0.};
+impl noise_density {
+    pub fn from(density: f64, scale: f64) -> noise_density {
+        noise_density::from_all(density, scale, 7, 0.)
+    }
 
-        let r_len  = r.dir.length();
-        let dist_in_boundary = (rec2.t - rec1.t) * r_len;
-        let hit_dist = self.neg_inv_density * rand_f64().ln();
+    pub fn from_all(density: f64, scale: f64, depth: i32, cutoff: f64) -> noise_density {
+        noise_density { noise: Perlin_Noise::new(), density, scale, depth, cutoff: cutoff.clamp(0., 0.99) }
+    }
+}
+
+impl Density for noise_density {
+    fn density(&self, p: &point3) -> f64 {
+        let turb = self.noise.turbulent_noise(&(*p * self.scale), self.depth);
+        self.density * ((turb - self.cutoff) / (1. - self.cutoff)).clamp(0., 1.)
+    }
+
+    fn majorant(&self) -> f64 { self.density }
+}
 
-        if hit_dist > dist_in_boundary {return false};
+/// Participating media with a density that changes inside the boundary (clouds, smoke...)
+/// Free flights are sampled with delta tracking: steps are taken as if the whole volume had the
+/// majorant density and each collision is real with probability density / majorant.
+/// Shadow rays go through the same hit, so light is blocked by real collisions only.
+pub struct heterogeneous_medium {
+    boundary: Box<dyn Hittable>,
+    density: Arc<dyn Density>,
+    phase_function: Arc<dyn Material>,
+}
 
-        rec.t = rec1.t + hit_dist / r_len;
-        rec.p = r.at(rec.t);
+impl heterogeneous_medium {
+    pub fn new(boundary: Box<dyn Hittable>, density: Arc<dyn Density>, tex: Arc<dyn Texture>) -> heterogeneous_medium {
+        let phase_function = Arc::new(isotropic::new(tex));
+        heterogeneous_medium { boundary, density, phase_function }
+    }
 
-        rec.n = vec3::up();
-        rec.front_face = true;
-        rec.mat = Arc::clone(&self.phase_function);
+    pub fn with_phase(boundary: Box<dyn Hittable>, density: Arc<dyn Density>, tex: Arc<dyn Texture>, phase: Arc<dyn PhaseFunction>) -> heterogeneous_medium {
+        let phase_function = Arc::new(phase_material::from(phase, tex));
+        heterogeneous_medium { boundary, density, phase_function }
+    }
 
-        true
+    /// emit is added at every real collision, so dense parts glow the most (fire, nebulas...)
+    pub fn emissive(boundary: Box<dyn Hittable>, density: Arc<dyn Density>, tex: Arc<dyn Texture>, phase: Arc<dyn PhaseFunction>, emit: Arc<dyn Texture>) -> heterogeneous_medium {
+        let phase_function = Arc::new(Medium_Emissive { phase: Arc::new(phase_material::from(phase, tex)), tex: emit });
+        heterogeneous_medium { boundary, density, phase_function }
     }
+}
 
+unsafe impl Sync for heterogeneous_medium {}
+unsafe impl Send for heterogeneous_medium {}
+
+impl Hittable for heterogeneous_medium {
+    fn hit(&self, r: &ray, t_min: f64, t_max: f64, rec:&mut hit_record) -> bool {
+        let majorant = self.density.majorant();
+        if majorant <= 0. { return false }
+
+        let step = 1. / (majorant * r.dir.length());
+        for (t0, t1) in boundary_intervals(self.boundary.as_ref(), r, t_min, t_max) {
+            let mut t = t0;
+            loop {
+                t -= (1. - rand_f64()).ln() * step;
+                if t >= t1 { break }
+
+                // Null collision otherwise, keep walking
+                let p = r.at(t);
+                if rand_f64() * majorant < self.density.density(&p) {
+                    rec.t = t;
+                    rec.p = p;
+                    rec.n = vec3::up();
+                    rec.front_face = true;
+                    rec.mat = Arc::clone(&self.phase_function);
+                    rec.uv = point2::new();
+                    rec.set_tangents(vec3::new(), vec3::new());
+                    return true
+                }
+            }
+        }
+
+        false
+    }
 
     fn get_aabb(&self, time0: f64, time1: f64) -> (bool, aabb) {
         self.boundary.get_aabb(time0, time1)
     }
-}
\ No newline at end of file
+}
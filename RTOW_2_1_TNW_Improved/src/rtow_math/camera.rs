@@ -175,4 +175,55 @@ impl camera {
         let dir = self.lower_left + self.pitch*u + self.yaw*v - self.origin - offset;
         ray::from_t(self.origin, dir, rand_f64_r(self._time0, self._time1))
     }
+
+    /// Where rays start from, the centre of the lens
+    pub fn position(&self) -> point3 {
+        self.origin
+    }
+
+    /// Unit direction the camera looks towards
+    pub fn forward(&self) -> vec3 {
+        self.to_film_center().unit_vec()
+    }
+
+    fn to_film_center(&self) -> vec3 {
+        self.lower_left + self.pitch / 2. + self.yaw / 2. - self.origin
+    }
+
+    /// (u, v) of ray() that goes through p, None if p is out of the view. The lens is taken as a pinhole
+    pub fn project(&self, p: &point3) -> Option<(f64, f64)> {
+        let to_center = self.to_film_center();
+        let d = *p - self.origin;
+        let depth = d.dot(&to_center.unit_vec());
+        if depth <= 0. { return None }
+
+        let on_film = d * (to_center.length() / depth) - to_center;
+        let u = on_film.dot(&self.pitch) / self.pitch.length_squared() + 0.5;
+        let v = on_film.dot(&self.yaw) / self.yaw.length_squared() + 0.5;
+        if u < 0. || u >= 1. || v < 0. || v >= 1. { return None }
+        Some((u, v))
+    }
+
+    /// Pdf by solid angle of ray() with a uniform (u, v) going along dir, 0 out of the view
+    pub fn pdf_dir(&self, dir: &vec3) -> f64 {
+        let to_center = self.to_film_center();
+        let cos = dir.unit_vec().dot(&to_center.unit_vec());
+        if cos <= 0. || self.project(&(self.origin + *dir)).is_none() { return 0. }
+        // Area of the film moved to distance 1 from the pinhole
+        let dist2 = to_center.length_squared();
+        let area = self.pitch.length() * self.yaw.length() / dist2;
+        1. / (area * cos * cos * cos)
+    }
+
+    /// focus_time_ray with differentials du, dv away (one pixel, in the same units as u, v)
+    pub fn focus_time_ray_diff(&self, u: f64, v: f64, du: f64, dv: f64) -> ray {
+        let mut r = self.focus_time_ray(u, v);
+        r.differential = Some(ray_differential {
+            rx_origin: r.origin,
+            rx_dir: r.dir + self.pitch * du,
+            ry_origin: r.origin,
+            ry_dir: r.dir + self.yaw * dv,
+        });
+        r
+    }
 }
\ No newline at end of file
@@ -3,7 +3,7 @@ pub use crate::rtow_math::vec3::*;
 
 pub use std::f64::INFINITY;
 
-pub const pi: f64 = 3.1415926535897932385;
+pub const pi: f64 = std::f64::consts::PI;
 
 pub fn deg_to_rad(degrees: f64) -> f64 {
     degrees * pi / 180.
@@ -0,0 +1,130 @@
+/// Piecewise constant distributions, to pick samples proportionally to tabulated values
+/// (environment maps by luminance, lights by power)
+
+/// 1D distribution over [0,1) made of func.len() equal steps
+pub struct distribution_1d {
+    pub func: Vec<f64>,
+    // cdf[i] is the probability of landing before step i, cdf[n] = 1
+    cdf: Vec<f64>,
+    // Integral of func over [0,1)
+    pub integral: f64,
+}
+
+impl distribution_1d {
+    /// Negative values count as 0, all zero falls back to uniform
+    pub fn from(func: Vec<f64>) -> distribution_1d {
+        if func.is_empty() { panic!("distribution_1d needs at least one value") }
+        let n = func.len() as f64;
+        let mut cdf = vec![0.; func.len() + 1];
+        for i in 0..func.len() {
+            cdf[i + 1] = cdf[i] + func[i].max(0.) / n;
+        }
+        let integral = cdf[func.len()];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0. { *c / integral } else { i as f64 / n };
+        }
+        distribution_1d { func, cdf, integral }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Step holding u in [0,1), the one with cdf[i] <= u < cdf[i+1]
+    fn find(&self, u: f64) -> usize {
+        let above = self.cdf.partition_point(|c| *c <= u);
+        above.saturating_sub(1).min(self.count() - 1)
+    }
+
+    /// Point in [0,1) for a uniform u, its pdf and the step it is in
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        let i = self.find(u);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0. { (u - self.cdf[i]) / width } else { 0. };
+        let x = ((i as f64 + du) / self.count() as f64).min(1. - f64::EPSILON);
+        (x, self.pdf(x), i)
+    }
+
+    /// A step for a uniform u, picked with probability discrete_pdf()
+    pub fn sample_discrete(&self, u: f64) -> usize {
+        self.find(u)
+    }
+
+    /// Density at x in [0,1)
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        if self.integral > 0. { self.func[i].max(0.) / self.integral } else { 1. }
+    }
+
+    pub fn discrete_pdf(&self, i: usize) -> f64 {
+        self.cdf[i + 1] - self.cdf[i]
+    }
+}
+
+/// 2D distribution over [0,1)^2 from values in rows: a marginal picks the row, then the row
+/// picks the column
+pub struct distribution_2d {
+    rows: Vec<distribution_1d>,
+    marginal: distribution_1d,
+}
+
+impl distribution_2d {
+    /// values[x + y * width], y is the row
+    pub fn from(values: &[f64], width: usize, height: usize) -> distribution_2d {
+        if values.len() < width * height { panic!("distribution_2d needs {}x{} values, got {}", width, height, values.len()) }
+        let rows: Vec<distribution_1d> = (0..height).map(|y| distribution_1d::from(values[y * width..(y + 1) * width].to_vec())).collect();
+        let marginal = distribution_1d::from(rows.iter().map(|row| row.integral).collect());
+        distribution_2d { rows, marginal }
+    }
+
+    /// (x, y) in [0,1)^2 for two uniform numbers, and its pdf
+    pub fn sample_continuous(&self, u0: f64, u1: f64) -> (f64, f64, f64) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(u1);
+        let (x, pdf_x, _) = self.rows[row].sample_continuous(u0);
+        (x, y, pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let row = ((y * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        self.marginal.pdf(y) * self.rows[row].pdf(x)
+    }
+}
+
+#[test]
+fn distribution_pdf_matches_sampling() {
+    // Stratified u, the share landing in each step has to be its pdf times its width
+    let n = 100000;
+    let d = distribution_1d::from(vec![1., 0., 3., 0.5, -2., 4.5]);
+    let mut counts = vec![0; d.count()];
+    for k in 0..n {
+        let (x, pdf, i) = d.sample_continuous((k as f64 + 0.5) / n as f64);
+        assert!((0. ..1.).contains(&x));
+        assert!((pdf - d.pdf(x)).abs() < 1e-12);
+        counts[i] += 1;
+    }
+    for i in 0..d.count() {
+        let expected = d.pdf((i as f64 + 0.5) / d.count() as f64) / d.count() as f64;
+        assert!((counts[i] as f64 / n as f64 - expected).abs() < 1e-4);
+        assert!((d.discrete_pdf(i) - expected).abs() < 1e-12);
+    }
+
+    let (w, h) = (4, 3);
+    let values = [0., 1., 2., 3., 4., 0., 0., 1., 0.5, 0.5, 8., 0.];
+    let d = distribution_2d::from(&values, w, h);
+    let total: f64 = values.iter().sum();
+    let mut counts = vec![0; w * h];
+    let side = 400;
+    for a in 0..side {
+        for b in 0..side {
+            let (x, y, pdf) = d.sample_continuous((a as f64 + 0.5) / side as f64, (b as f64 + 0.5) / side as f64);
+            assert!((pdf - d.pdf(x, y)).abs() < 1e-9);
+            counts[(x * w as f64) as usize + (y * h as f64) as usize * w] += 1;
+        }
+    }
+    for i in 0..w * h {
+        assert!((counts[i] as f64 / (side * side) as f64 - values[i] / total).abs() < 1e-3);
+        // The pdf is per unit area, each cell covers 1 / (w * h)
+        let (x, y) = (((i % w) as f64 + 0.5) / w as f64, ((i / w) as f64 + 0.5) / h as f64);
+        assert!((d.pdf(x, y) / (w * h) as f64 - values[i] / total).abs() < 1e-12);
+    }
+}
@@ -4,4 +4,8 @@ pub mod defines;
 pub mod rng;
 pub mod camera;
 pub mod vec2;
+pub mod onb;
+pub mod roots;
+pub mod spectrum;
+pub mod distribution;
 pub mod prelude;
\ No newline at end of file
@@ -0,0 +1,29 @@
+use crate::rtow_math::vec3::*;
+
+/// Orthonormal basis, w is the "main" axis (normal, cylinder axis...)
+/// local() goes from basis space to world, to_local() the other way around
+#[derive(Debug, Copy, Clone)]
+pub struct onb {
+    pub u: vec3,
+    pub v: vec3,
+    pub w: vec3,
+}
+
+impl onb {
+    pub fn build_from_w(n: &vec3) -> onb {
+        let w = n.unit_vec();
+        // Pick whatever world axis is less aligned with w so the cross does not degenerate
+        let a = if w.x().abs() > 0.9 { vec3::from(0., 1., 0.) } else { vec3::from(1., 0., 0.) };
+        let v = w.cross(&a).unit_vec();
+        let u = w.cross(&v);
+        onb { u, v, w }
+    }
+
+    pub fn local(&self, a: &vec3) -> vec3 {
+        self.u * a.v[0] + self.v * a.v[1] + self.w * a.v[2]
+    }
+
+    pub fn to_local(&self, a: &vec3) -> vec3 {
+        vec3::from(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}
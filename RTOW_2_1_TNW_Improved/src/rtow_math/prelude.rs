@@ -4,3 +4,7 @@ pub use crate::rtow_math::defines::*;
 pub use crate::rtow_math::rng::*;
 pub use crate::rtow_math::camera::*;
 pub use crate::rtow_math::vec2::*;
+pub use crate::rtow_math::onb::*;
+pub use crate::rtow_math::roots::*;
+pub use crate::rtow_math::spectrum::*;
+pub use crate::rtow_math::distribution::*;
@@ -1,16 +1,28 @@
 use crate::rtow_math::vec3::*;
 
-#[derive(PartialEq)]
+/// Rays through the neighbour pixels (x to the right, y up), they tell how big a pixel is
+/// where the ray lands so textures can be filtered. Only camera rays and specular bounces have them
+#[derive(PartialEq, Copy, Clone)]
+pub struct ray_differential {
+    pub rx_origin: point3,
+    pub rx_dir: vec3,
+    pub ry_origin: point3,
+    pub ry_dir: vec3,
+}
+
+#[derive(PartialEq, Clone)]
 pub struct ray {
     pub origin: point3,
     pub dir: vec3,
     pub time: f64, // Add Time for motion blur -> SpatioTemporal ray tracing
+    pub wavelength: f64, // nm, only set by the spectral integrator, 0 means rgb
+    pub differential: Option<ray_differential>,
 }
 
 impl ray {
-    pub fn new() -> ray { ray{origin: point3::new(), dir: vec3::new(), time: 0.}}
-    pub fn from(p: point3, d: vec3) -> ray { ray {origin: p, dir: d, time: 0. }}
-    pub fn from_t(p: point3, d: vec3, time: f64) -> ray { ray { origin: p, dir: d, time } }
+    pub fn new() -> ray { ray{origin: point3::new(), dir: vec3::new(), time: 0., wavelength: 0., differential: None}}
+    pub fn from(p: point3, d: vec3) -> ray { ray {origin: p, dir: d, time: 0., wavelength: 0., differential: None }}
+    pub fn from_t(p: point3, d: vec3, time: f64) -> ray { ray { origin: p, dir: d, time, wavelength: 0., differential: None } }
     pub fn at(&self, t: f64) -> point3 { 
         self.origin + self.dir * t 
     }
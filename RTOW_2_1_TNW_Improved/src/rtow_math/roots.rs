@@ -0,0 +1,118 @@
+//! Real roots of polynomials up to degree 4, returned sorted from lowest to highest
+//! Used by the quadrics (quadratic) and the torus (quartic)
+
+const EPS: f64 = 1e-12;
+
+/// a*x^2 + b*x + c = 0
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a.abs() < EPS {
+        if b.abs() < EPS { return Vec::new() }
+        return vec![-c / b];
+    }
+
+    let discriminant = b*b - 4.*a*c;
+    if discriminant < 0. { return Vec::new() }
+
+    // Avoid the cancellation of -b +- sqrt(d) when b^2 >> 4ac
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let (mut r0, mut r1) = if q.abs() < EPS { (0., 0.) } else { (q / a, c / q) };
+    if r0 > r1 { std::mem::swap(&mut r0, &mut r1) };
+
+    vec![r0, r1]
+}
+
+/// x^3 + a*x^2 + b*x + c = 0
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // Depressed cubic y^3 + p*y + q = 0 with x = y - a/3
+    let sub = a / 3.;
+    let p = b - a*a / 3.;
+    let q = 2.*a*a*a / 27. - a*b / 3. + c;
+
+    let half_q = q / 2.;
+    let third_p = p / 3.;
+    let discriminant = half_q*half_q + third_p*third_p*third_p;
+
+    let mut ret = if discriminant.abs() < EPS {
+        if half_q.abs() < EPS {
+            vec![0.]
+        } else {
+            let u = (-half_q).cbrt();
+            vec![2.*u, -u]
+        }
+    } else if discriminant < 0. {
+        // Three real roots -> trigonometric method
+        let phi = (-half_q / (-third_p*third_p*third_p).sqrt()).clamp(-1., 1.).acos() / 3.;
+        let m = 2. * (-third_p).sqrt();
+        vec![
+            m * phi.cos(),
+            m * (phi + 2. * std::f64::consts::PI / 3.).cos(),
+            m * (phi - 2. * std::f64::consts::PI / 3.).cos(),
+        ]
+    } else {
+        let sq = discriminant.sqrt();
+        vec![(sq - half_q).cbrt() - (sq + half_q).cbrt()]
+    };
+
+    for r in ret.iter_mut() { *r -= sub };
+    ret.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    ret
+}
+
+/// x^4 + a*x^3 + b*x^2 + c*x + d = 0 by Ferrari's method
+/// Every root is polished with Newton on the original polynomial, the closed form
+/// alone loses too much precision for thin tori seen from far away
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // Depressed quartic y^4 + p*y^2 + q*y + r = 0 with x = y - a/4
+    let sub = a / 4.;
+    let aa = a*a;
+    let p = b - 3.*aa / 8.;
+    let q = c - a*b / 2. + aa*a / 8.;
+    let r = d - a*c / 4. + aa*b / 16. - 3.*aa*aa / 256.;
+
+    let mut ys: Vec<f64> = Vec::new();
+    if q.abs() < EPS {
+        // Biquadratic, solve for z = y^2
+        for z in solve_quadratic(1., p, r) {
+            if z < 0. { continue };
+            let s = z.sqrt();
+            ys.push(s);
+            ys.push(-s);
+        }
+    } else {
+        // Resolvent cubic m^3 + p*m^2 + (p^2/4 - r)*m - q^2/8 = 0 always has a positive root when q != 0
+        let m = solve_cubic(p, p*p / 4. - r, -q*q / 8.).into_iter().fold(0., f64::max);
+        if m <= 0. { return Vec::new() }
+        let s = (2.*m).sqrt();
+        ys.append(&mut solve_quadratic(1., -s, p / 2. + m + q / (2.*s)));
+        ys.append(&mut solve_quadratic(1., s, p / 2. + m - q / (2.*s)));
+    }
+
+    let mut ret: Vec<f64> = ys.into_iter().map(|y| {
+        let mut x = y - sub;
+        for _ in 0..4 {
+            let f = (((x + a)*x + b)*x + c)*x + d;
+            let df = ((4.*x + 3.*a)*x + 2.*b)*x + c;
+            if df.abs() < EPS { break };
+            x -= f / df;
+        }
+        x
+    }).collect();
+
+    ret.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    ret
+}
+
+#[test]
+fn quartic_roots() {
+    // (x-1)(x-2)(x-3)(x-4) = x^4 - 10x^3 + 35x^2 - 50x + 24
+    let roots = solve_quartic(-10., 35., -50., 24.);
+    assert_eq!(roots.len(), 4);
+    for (r, expected) in roots.iter().zip([1., 2., 3., 4.]) {
+        assert!((r - expected).abs() < 1e-9);
+    }
+
+    // (x^2 + 1)(x - 0.5)(x + 2) only has 2 real roots
+    let roots = solve_quartic(1.5, 0., 1.5, -1.);
+    assert_eq!(roots.len(), 2);
+    assert!((roots[0] + 2.).abs() < 1e-9 && (roots[1] - 0.5).abs() < 1e-9);
+}
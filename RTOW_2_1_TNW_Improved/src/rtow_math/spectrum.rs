@@ -0,0 +1,110 @@
+use crate::rtow_math::vec3::*;
+
+/// Visible range sampled by the spectral integrator, in nm
+pub const LAMBDA_MIN: f64 = 380.;
+pub const LAMBDA_MAX: f64 = 720.;
+
+/// Piecewise gaussian from the CIE fits, different width at each side of the peak
+fn cie_lobe(lambda: f64, mu: f64, sigma_low: f64, sigma_high: f64) -> f64 {
+    let t = (lambda - mu) / if lambda < mu { sigma_low } else { sigma_high };
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 colour matching functions, multi-lobe fit of Wyman, Sloan and Shirley 2013
+pub fn cie_xyz(lambda: f64) -> vec3 {
+    vec3::from(
+        1.056 * cie_lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * cie_lobe(lambda, 442.0, 16.0, 26.7) - 0.065 * cie_lobe(lambda, 501.1, 20.4, 26.2),
+        0.821 * cie_lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * cie_lobe(lambda, 530.9, 16.3, 31.1),
+        1.217 * cie_lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * cie_lobe(lambda, 459.0, 26.0, 13.8))
+}
+
+/// XYZ to linear sRGB (D65)
+pub fn xyz_to_srgb(xyz: &vec3) -> colorRGB {
+    let (x, y, z) = (xyz.v[0], xyz.v[1], xyz.v[2]);
+    colorRGB::from(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z)
+}
+
+/// Spectral radiance of a black body at temperature kelvin, lambda in nm (Planck's law, SI units)
+pub fn planck(lambda: f64, kelvin: f64) -> f64 {
+    let (h, c, k) = (6.62607015e-34, 2.99792458e8, 1.380649e-23);
+    let l = lambda * 1e-9;
+    2. * h * c * c / (l.powi(5) * ((h * c / (l * k * kelvin)).exp() - 1.))
+}
+
+/// Linear sRGB colour of a black body, normalized to a luminance of 1
+/// Around 6500K is white, candles are ~1900K, tungsten bulbs ~2700K, clear sky ~10000K
+pub fn blackbody(kelvin: f64) -> colorRGB {
+    let steps = 200;
+    let d_lambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+    let mut xyz = vec3::new();
+    for i in 0..steps {
+        let lambda = LAMBDA_MIN + (i as f64 + 0.5) * d_lambda;
+        xyz = xyz + cie_xyz(lambda) * planck(lambda, kelvin);
+    }
+    // Very hot or cold colours fall out of sRGB, keep the closest one we can show
+    let rgb = xyz_to_srgb(&(xyz / xyz.v[1]));
+    colorRGB::from(rgb.v[0].max(0.), rgb.v[1].max(0.), rgb.v[2].max(0.))
+}
+
+/// Linear sRGB of a flat spectrum of 1 over the sampled range
+/// Dividing by it keeps white as white, as the rgb materials expect
+pub fn spectral_white() -> colorRGB {
+    let steps = 1000;
+    let d_lambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+    let mut xyz = vec3::new();
+    for i in 0..steps {
+        xyz = xyz + cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * d_lambda) * d_lambda;
+    }
+    xyz_to_srgb(&xyz)
+}
+
+// Smits 1999 basis spectra, 10 bins over [LAMBDA_MIN, LAMBDA_MAX]
+const SMITS_WHITE: [f64; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// Value at lambda of a smooth spectrum with the given rgb (Smits 1999)
+/// Works for reflectances and emission alike, emission is just scaled up
+pub fn rgb_to_spectrum(rgb: &colorRGB, lambda: f64) -> f64 {
+    let bin = (((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.) as i64).clamp(0, 9) as usize;
+    let (r, g, b) = (rgb.v[0], rgb.v[1], rgb.v[2]);
+
+    // White up to the smallest channel, then the secondary and primary that make up the rest
+    if r <= g && r <= b {
+        r * SMITS_WHITE[bin] + if g <= b {
+            (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        }
+    } else if g <= r && g <= b {
+        g * SMITS_WHITE[bin] + if r <= b {
+            (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        }
+    } else {
+        b * SMITS_WHITE[bin] + if r <= g {
+            (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        } else {
+            (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        }
+    }
+}
+
+/// Hero wavelength plus 3 more evenly rotated over the range, all with pdf 1 / range
+pub fn sample_wavelengths() -> [f64; 4] {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let hero = crate::rtow_math::rng::rand_f64() * range;
+    let mut ret = [0.; 4];
+    for (i, l) in ret.iter_mut().enumerate() {
+        *l = LAMBDA_MIN + (hero + i as f64 * range / 4.) % range;
+    }
+    ret
+}
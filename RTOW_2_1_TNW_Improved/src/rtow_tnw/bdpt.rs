@@ -0,0 +1,465 @@
+use simple_stopwatch::Stopwatch;
+
+use crate::objects::prelude::*;
+use crate::rtow_math::prelude::*;
+use crate::materials::prelude::*;
+use std::sync::*;
+
+use rayon::prelude::*;
+
+// Bidirectional path tracer (Veach 1997, laid out as in PBRT)
+// Each sample traces a camera subpath and a light subpath from a point on an area light, then
+// joins every prefix of one with every prefix of the other. The same path can come from many of
+// these strategies, each one is weighted by multiple importance sampling (power heuristic) over
+// the pdfs all of them would have had, so each light path finds what it is best at:
+// caustics through dielectrics come from light subpaths, big lights from the camera side.
+// Strategies with a single camera vertex (light tracing) land anywhere on the film and are
+// splatted into the image instead of the pixel being sampled.
+// Only area lights (sample_surface()) start light subpaths, the background is only reached by
+// camera subpaths. Delta lights (points, spots, directional) can't be hit or started from, each
+// camera vertex is joined to one of them with sample_delta() (s = 1 for that light). Nothing else
+// can find them so those connections take no mis weight. Emitters of the world left out of the
+// lights (emissive media, objects not in the list) are only found by camera subpaths, s = 0 is
+// the one strategy for them and takes it all. The camera is taken as a pinhole.
+
+// Bounces of each subpath, the connections grow with the square of it
+const BDPT_DEPTH: usize = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexType {
+    Camera,
+    Light,
+    Surface,
+}
+
+/// Vertex of a subpath. pdf_fwd is the pdf by area of reaching it from the previous vertex of its
+/// own subpath, pdf_rev the pdf of reaching it from the next one, as the other subpath would
+#[derive(Clone)]
+struct path_vertex {
+    vtype: VertexType,
+    // For the camera p is the pinhole and n where it looks, for lights n is the outward normal
+    rec: hit_record,
+    // Ray that got to this vertex, surfaces only
+    r_in: ray,
+    // Throughput of the subpath up to here, this vertex's own scattering not included
+    beta: colorRGB,
+    // Specular scattering here, can't be joined to anything
+    delta: bool,
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl path_vertex {
+    fn p(&self) -> point3 {
+        self.rec.p
+    }
+}
+
+/// Lights that can start a light subpath, picked proportionally to their power, and the delta
+/// lights apart
+struct light_set {
+    lights: Vec<Arc<dyn Hittable>>,
+    distribution: Option<distribution_1d>,
+    deltas: Vec<Arc<dyn Hittable>>,
+}
+
+impl light_set {
+    /// Panics on a bounded light that is neither a delta light nor sample_surface()-able, it would be
+    /// dropped without a word otherwise. Unbounded ones (environment, sky) are the background
+    fn from(all: &hittable_list) -> light_set {
+        for (idx, light) in all.obj_list.iter().enumerate() {
+            let bounded = light.get_aabb(0., 1.).0;
+            if bounded && !light.is_delta() && (light.light_bounds().is_none() || light.sample_surface().is_none()) {
+                panic!("bdpt: light {} can't start light subpaths, it needs an emissive material, light_bounds() and sample_surface() (aa_box and media don't have them)", idx);
+            }
+        }
+        let lights: Vec<Arc<dyn Hittable>> = all.obj_list.iter().filter(|l| !l.is_delta() && l.light_bounds().is_some()).map(Arc::clone).collect();
+        let deltas: Vec<Arc<dyn Hittable>> = all.obj_list.iter().filter(|l| l.is_delta()).map(Arc::clone).collect();
+        let power: Vec<f64> = lights.iter().map(|l| l.light_bounds().map_or(0., |lb| lb.phi)).collect();
+        let distribution = if power.is_empty() { None } else { Some(distribution_1d::from(power)) };
+        light_set { lights, distribution, deltas }
+    }
+
+    fn pick(&self, u: f64) -> Option<(usize, f64)> {
+        let distribution = self.distribution.as_ref()?;
+        let idx = distribution.sample_discrete(u);
+        Some((idx, distribution.discrete_pdf(idx)))
+    }
+
+    /// Pdf by area of a light subpath starting at the emitter point v, found by a camera subpath.
+    /// The hit doesn't say which object it was, each light is tried along the same ray
+    fn origin_pdf(&self, v: &path_vertex) -> f64 {
+        let Some(distribution) = self.distribution.as_ref() else { return 0. };
+        let (t_min, t_max) = (v.rec.t * (1. - 1e-7) - 1e-9, v.rec.t * (1. + 1e-7) + 1e-9);
+        let mut pdf = 0.;
+        for (idx, light) in self.lights.iter().enumerate() {
+            let mut rec = hit_record::new();
+            if light.hit(&v.r_in, t_min, t_max, &mut rec) {
+                pdf += distribution.discrete_pdf(idx) * light.surface_pdf(&v.p());
+            }
+        }
+        pdf
+    }
+}
+
+/// rec as seen by a ray r arriving at it, front_face and n turned to r's side
+fn reoriented(rec: &hit_record, r: &ray) -> hit_record {
+    let mut ret = rec.clone();
+    let outward = if rec.front_face { rec.n } else { rec.n * -1. };
+    ret.front_face = r.dir.dot(&outward) < 0.;
+    ret.n = if ret.front_face { outward } else { outward * -1. };
+    ret
+}
+
+/// Light an emitter sends from rec towards dir
+fn emitted_towards(rec: &hit_record, dir: &vec3) -> colorRGB {
+    let r = ray::from(rec.p + *dir, *dir * -1.);
+    let rec = reoriented(rec, &r);
+    rec.mat.emitted_hit(&r, &rec)
+}
+
+/// Pdf by solid angle of the direction a light subpath leaves the emitter rec by:
+/// cosine weighted out of the front face, or out of either face for two sided emitters
+fn emission_pdf(rec: &hit_record, dir: &vec3) -> f64 {
+    let Some((_, two_sided)) = rec.mat.light_emission() else { return 0. };
+    let outward = if rec.front_face { rec.n } else { rec.n * -1. };
+    let cos = outward.dot(&dir.unit_vec());
+    if two_sided { return cos.abs() / (2. * pi) }
+    if cos > 0. { cos / pi } else { 0. }
+}
+
+/// Pdf by solid angle from a vertex to a pdf by area at the vertex to
+fn density_to_area(pdf_dir: f64, from: &path_vertex, to: &path_vertex) -> f64 {
+    let d = to.p() - from.p();
+    let dist2 = d.length_squared();
+    if dist2 <= 0. { return 0. }
+    pdf_dir * to.rec.n.dot(&d).abs() / (dist2 * dist2.sqrt())
+}
+
+/// Pdf by area of sampling next from v, having got to v from prev
+fn vertex_pdf(cam: &camera, v: &path_vertex, prev: Option<&path_vertex>, next: &path_vertex) -> f64 {
+    let dir = next.p() - v.p();
+    let pdf_dir = match v.vtype {
+        VertexType::Camera => cam.pdf_dir(&dir),
+        VertexType::Light => emission_pdf(&v.rec, &dir),
+        VertexType::Surface => {
+            let Some(prev) = prev else { return 0. };
+            let r_in = ray::from(prev.p(), v.p() - prev.p());
+            v.rec.mat.scattering_pdf(&r_in, &reoriented(&v.rec, &r_in), &ray::from(v.p(), dir))
+        },
+    };
+    density_to_area(pdf_dir, v, next)
+}
+
+/// Extends path from its last vertex along r, beta and pdf_dir being the throughput and the pdf
+/// by solid angle of r. Returns what the camera sees if the path leaves the scene (bg is None for
+/// light subpaths)
+fn random_walk(world: &hittable_list, mut r: ray, mut beta: colorRGB, mut pdf_dir: f64, path: &mut Vec<path_vertex>, bg: Option<&dyn Background>) -> colorRGB {
+    for _ in 0..BDPT_DEPTH {
+        let mut rec = hit_record::new();
+        if !world.hit_bvh(0.0001, INFINITY, &mut rec, &r) {
+            return match bg { Some(bg) => beta * bg.value(&r.dir), None => colorRGB::new() }
+        }
+        rec.set_footprint(&r);
+
+        let mut v = path_vertex { vtype: VertexType::Surface, rec, r_in: r.clone(), beta, delta: false, pdf_fwd: 0., pdf_rev: 0. };
+        v.pdf_fwd = density_to_area(pdf_dir, &path[path.len() - 1], &v);
+        path.push(v);
+        let n = path.len();
+
+        let mut attenuation = colorRGB::new();
+        let mut scattered = ray::new();
+        if !path[n - 1].rec.mat.scatter_tex(&r, &path[n - 1].rec, &mut attenuation, &mut scattered) { break }
+
+        // Pdf both ways through this vertex, the reverse one goes to the previous vertex
+        pdf_dir = path[n - 1].rec.mat.scattering_pdf(&r, &path[n - 1].rec, &scattered);
+        path[n - 1].delta = pdf_dir <= 0.;
+        let pdf_rev_dir = if path[n - 1].delta { 0. } else {
+            let back_in = ray::from(path[n - 1].p() + scattered.dir, scattered.dir * -1.);
+            let back_out = ray::from(path[n - 1].p(), path[n - 2].p() - path[n - 1].p());
+            path[n - 1].rec.mat.scattering_pdf(&back_in, &reoriented(&path[n - 1].rec, &back_in), &back_out)
+        };
+        path[n - 2].pdf_rev = density_to_area(pdf_rev_dir, &path[n - 1], &path[n - 2]);
+
+        beta = beta * attenuation;
+        r = scattered;
+        if beta.near_zero() { break }
+    }
+    colorRGB::new()
+}
+
+/// Camera subpath for film position (u, v), and the light it sees leaving the scene
+fn camera_subpath(world: &hittable_list, cam: &camera, bg: &dyn Background, u: f64, v: f64) -> (Vec<path_vertex>, colorRGB) {
+    let r = cam.time_ray(u, v);
+    let mut rec = hit_record::new();
+    rec.p = cam.position();
+    rec.n = cam.forward();
+    let mut path = vec![path_vertex { vtype: VertexType::Camera, rec, r_in: ray::new(), beta: colorRGB::one(), delta: false, pdf_fwd: 1., pdf_rev: 0. }];
+    let pdf_dir = cam.pdf_dir(&r.dir);
+    let escaped = random_walk(world, r, colorRGB::one(), pdf_dir, &mut path, Some(bg));
+    (path, escaped)
+}
+
+fn light_subpath(world: &hittable_list, lights: &light_set, time: f64) -> Vec<path_vertex> {
+    let mut path = Vec::new();
+    let Some((idx, pmf)) = lights.pick(rand_f64()) else { return path };
+    let Some((rec, pdf_area)) = lights.lights[idx].sample_surface() else { return path };
+    let Some((_, two_sided)) = rec.mat.light_emission() else { return path };
+    if pdf_area <= 0. { return path }
+
+    // Cosine weighted like lambertian scattering, as emission_pdf() expects
+    let side = if two_sided && rand_f64() < 0.5 { rec.n * -1. } else { rec.n };
+    let mut dir = side + random_unit_vector();
+    if dir.near_zero() { dir = side }
+    let pdf_dir = emission_pdf(&rec, &dir);
+    let le = emitted_towards(&rec, &dir);
+    let cos = rec.n.dot(&dir.unit_vec()).abs();
+
+    let beta = colorRGB::one() / (pmf * pdf_area);
+    let r = ray::from_t(rec.p, dir, time);
+    path.push(path_vertex { vtype: VertexType::Light, rec, r_in: ray::new(), beta, delta: false, pdf_fwd: pmf * pdf_area, pdf_rev: 0. });
+    if pdf_dir <= 0. || le.near_zero() { return path }
+
+    random_walk(world, r, beta * le * (cos / pdf_dir), pdf_dir, &mut path, None);
+    path
+}
+
+/// Nothing between a and b
+fn unoccluded(world: &hittable_list, a: &point3, b: &point3, time: f64) -> bool {
+    let d = *b - *a;
+    let dist = d.length();
+    let mut rec = hit_record::new();
+    !world.hit_bvh(0.0001, dist - 0.0001, &mut rec, &ray::from_t(*a, d / dist, time))
+}
+
+/// What the vertex v sends towards p: emission times the cosine for a light, the bsdf times the
+/// cosine for anything else
+fn vertex_f(v: &path_vertex, p: &point3) -> colorRGB {
+    let dir = *p - v.p();
+    match v.vtype {
+        VertexType::Light => emitted_towards(&v.rec, &dir) * v.rec.n.dot(&dir.unit_vec()).abs(),
+        VertexType::Surface => v.rec.mat.eval(&v.r_in, &v.rec, &ray::from(v.p(), dir)),
+        VertexType::Camera => colorRGB::new(),
+    }
+}
+
+/// Light of a delta light, picked uniformly, reaching the camera vertex pt and scattered by it
+fn connect_delta(world: &hittable_list, lights: &light_set, pt: &path_vertex, time: f64) -> colorRGB {
+    if lights.deltas.is_empty() { return colorRGB::new() }
+    let count = lights.deltas.len();
+    let light = &lights.deltas[((rand_f64() * count as f64) as usize).min(count - 1)];
+    let Some((dir, dist, incoming)) = light.sample_delta(&pt.p()) else { return colorRGB::new() };
+
+    let dir = dir.unit_vec();
+    let f = vertex_f(pt, &(pt.p() + dir));
+    if f.near_zero() { return colorRGB::new() }
+    let mut blocker = hit_record::new();
+    if world.hit_bvh(0.0001, dist - 0.0001, &mut blocker, &ray::from_t(pt.p(), dir, time)) { return colorRGB::new() }
+    pt.beta * f * incoming * count as f64
+}
+
+/// Joins the first t camera vertices with the first s light vertices. Returns the unweighted light
+/// and, for t = 1, the film position it lands on
+fn connect(world: &hittable_list, cam: &camera, cam_path: &[path_vertex], light_path: &[path_vertex], s: usize, t: usize, time: f64) -> Option<(colorRGB, Option<(f64, f64)>)> {
+    let pt = &cam_path[t - 1];
+    if s == 0 {
+        // The camera subpath found a light by itself
+        let le = pt.rec.mat.emitted_hit(&pt.r_in, &pt.rec);
+        if le.near_zero() { return None }
+        return Some((pt.beta * le, None))
+    }
+
+    let qs = &light_path[s - 1];
+    if qs.delta || pt.delta { return None }
+    if t == 1 {
+        // Light tracing, straight to the pinhole
+        let uv = cam.project(&qs.p())?;
+        let d = qs.p() - pt.p();
+        let f = vertex_f(qs, &pt.p());
+        if f.near_zero() || !unoccluded(world, &qs.p(), &pt.p(), time) { return None }
+        let importance = cam.pdf_dir(&d) / d.length_squared();
+        return Some((qs.beta * f * importance, Some(uv)))
+    }
+
+    let (f_cam, f_light) = (vertex_f(pt, &qs.p()), vertex_f(qs, &pt.p()));
+    if f_cam.near_zero() || f_light.near_zero() { return None }
+    if !unoccluded(world, &pt.p(), &qs.p(), time) { return None }
+    let dist2 = (qs.p() - pt.p()).length_squared();
+    Some((pt.beta * f_cam * qs.beta * f_light / dist2, None))
+}
+
+/// Power heuristic weight of strategy (s, t) against every other one that could make the same path
+fn mis_weight(cam: &camera, lights: &light_set, cam_path: &[path_vertex], light_path: &[path_vertex], s: usize, t: usize) -> f64 {
+    if s + t == 2 { return 1. }
+    let pt = &cam_path[t - 1];
+    let qs = if s > 0 { Some(&light_path[s - 1]) } else { None };
+    let pt_minus = if t > 1 { Some(&cam_path[t - 2]) } else { None };
+    let qs_minus = if s > 1 { Some(&light_path[s - 2]) } else { None };
+
+    // The connection changes the reverse pdfs of the vertices at both of its ends
+    let pt_rev = match qs {
+        Some(qs) => vertex_pdf(cam, qs, qs_minus, pt),
+        None => lights.origin_pdf(pt),
+    };
+    // An emitter no light subpath can start from, nothing else would find this path
+    if s == 0 && pt_rev <= 0. { return 1. }
+    let pt_minus_rev = match (pt_minus, qs) {
+        (Some(pt_minus), Some(qs)) => vertex_pdf(cam, pt, Some(qs), pt_minus),
+        (Some(pt_minus), None) => density_to_area(emission_pdf(&pt.rec, &(pt_minus.p() - pt.p())), pt, pt_minus),
+        _ => 0.,
+    };
+    let qs_rev = match qs { Some(qs) => vertex_pdf(cam, pt, pt_minus, qs), None => 0. };
+    let qs_minus_rev = match (qs, qs_minus) { (Some(qs), Some(qs_minus)) => vertex_pdf(cam, qs, Some(pt), qs_minus), _ => 0. };
+
+    let cam_rev = |i: usize| if i + 1 == t { pt_rev } else if i + 2 == t { pt_minus_rev } else { cam_path[i].pdf_rev };
+    let light_rev = |i: usize| if i + 1 == s { qs_rev } else if i + 2 == s { qs_minus_rev } else { light_path[i].pdf_rev };
+    // Delta pdfs are 0, they cancel out in the ratios
+    let remap0 = |x: f64| if x != 0. { x } else { 1. };
+
+    let mut sum_ri = 0.;
+    let mut ri = 1.;
+    for i in (1..t).rev() {
+        ri *= remap0(cam_rev(i)) / remap0(cam_path[i].pdf_fwd);
+        let delta = |j: usize| j + 1 != t && cam_path[j].delta;
+        if !delta(i) && !delta(i - 1) { sum_ri += ri * ri }
+    }
+    ri = 1.;
+    for i in (0..s).rev() {
+        ri *= remap0(light_rev(i)) / remap0(light_path[i].pdf_fwd);
+        let delta = |j: usize| j + 1 != s && light_path[j].delta;
+        let delta_before = i > 0 && delta(i - 1);
+        if !delta(i) && !delta_before { sum_ri += ri * ri }
+    }
+    1. / (1. + sum_ri)
+}
+
+/// One sample of the pixel at film position (u, v), and the light tracing splats it made elsewhere
+fn bdpt_sample(world: &hittable_list, lights: &light_set, cam: &camera, bg: &dyn Background, u: f64, v: f64) -> (colorRGB, Vec<((f64, f64), colorRGB)>) {
+    let (cam_path, escaped) = camera_subpath(world, cam, bg, u, v);
+    let time = cam_path.get(1).map_or(0., |v| v.r_in.time);
+    let light_path = light_subpath(world, lights, time);
+
+    let mut col = escaped;
+    for pt in cam_path.iter().skip(1) {
+        if !pt.delta { col = col + connect_delta(world, lights, pt, time) }
+    }
+
+    let mut splats = Vec::new();
+    for t in 1..=cam_path.len() {
+        for s in 0..=light_path.len() {
+            // Nothing to join for (0, 1), and lights seen straight away are left to (0, 2)
+            if t == 1 && s <= 1 { continue }
+            let Some((l, film)) = connect(world, cam, &cam_path, &light_path, s, t, time) else { continue };
+            let weighted = l * mis_weight(cam, lights, &cam_path, &light_path, s, t);
+            match film {
+                Some(uv) => splats.push((uv, weighted)),
+                None => col = col + weighted,
+            }
+        }
+    }
+    (col, splats)
+}
+
+use crate::rtow_tnw::*;
+
+pub fn render() {
+    let mut timer = Stopwatch::start_new();
+
+    let (cam, image_width, image_height) = cam_cornell_box();
+    let (iw_f64, ih_f64) = (image_width as f64, image_height as f64);
+    let bg_col = colorRGB::new();
+
+    let (world, lights, material_vec) = obj_glass_cornell_box();
+    let lights = light_set::from(&lights);
+
+    println!("P3\n{} {}\n255\n", image_width, image_height);
+
+    // Rows from the top like the output, film v goes up
+    let rows: Vec<i32> = (0..image_height).rev().collect();
+    let traced: Vec<(Vec<colorRGB>, Vec<((f64, f64), colorRGB)>)> = rows.into_par_iter().map(|i| {
+        let mut row = vec![colorRGB::new(); image_width as usize];
+        let mut row_splats = Vec::new();
+        for j in 0..image_width {
+            for s in 0..samples {
+                let u = (j as f64 + rand_f64()) / iw_f64;
+                let v = (i as f64 + rand_f64()) / ih_f64;
+                let (col, splats) = bdpt_sample(&world, &lights, &cam, &bg_col, u, v);
+                row[j as usize] = row[j as usize] + col;
+                row_splats.extend(splats);
+            }
+        }
+        (row, row_splats)
+    }).collect();
+
+    eprintln!("Tasks finished running at {} ms", timer.ms());
+
+    // Every pixel sample traced one light subpath, the splats of all of them share the image
+    let mut image: Vec<colorRGB> = Vec::with_capacity((image_width * image_height) as usize);
+    for (row, _) in traced.iter() { image.extend(row.iter().copied()) }
+    for (_, row_splats) in traced.iter() {
+        for ((u, v), col) in row_splats.iter() {
+            let (j, i) = ((u * iw_f64) as usize, (v * ih_f64) as usize);
+            let idx = (image_height as usize - 1 - i.min(image_height as usize - 1)) * image_width as usize + j.min(image_width as usize - 1);
+            image[idx] = image[idx] + *col;
+        }
+    }
+
+    for col in image.iter() {
+        col.write_color(samples as f64);
+    }
+
+    eprintln!("Took {} ms", timer.ms());
+}
+
+/// Whole film averages of BDPT and of the unidirectional path tracer, by luminance
+fn film_means(world: &hittable_list, bdpt_lights: &hittable_list, path_lights: &hittable_list, n: usize) -> (f64, f64) {
+    let (cam, _, _) = cam_cornell_box();
+    let light_set = light_set::from(bdpt_lights);
+    let bg = colorRGB::new();
+    let lum = |c: colorRGB| 0.2126 * c.v[0] + 0.7152 * c.v[1] + 0.0722 * c.v[2];
+
+    let bdpt = (0..n).into_par_iter().map(|_| {
+        let (col, splats) = bdpt_sample(world, &light_set, &cam, &bg, rand_f64(), rand_f64());
+        lum(col) + splats.iter().map(|(_, c)| lum(*c)).sum::<f64>()
+    }).sum::<f64>() / n as f64;
+    let path = (0..n).into_par_iter().map(|_| {
+        lum(light_sampling::path_color(&cam.time_ray(rand_f64(), rand_f64()), world, path_lights, &bg, 12))
+    }).sum::<f64>() / n as f64;
+    (bdpt, path)
+}
+
+#[test]
+fn bdpt_mean_matches_path_tracing() {
+    // Both are noisy, about 2.5% apart at this count. Leaving out a term of the mis weights is
+    // off by much more, taking an unlisted emitter for one bdpt can start from halves it
+    let n = 50000;
+    let close = |(bdpt, path): (f64, f64), what: &str| {
+        assert!((bdpt - path).abs() < 0.15 * path, "{}: bdpt {} path {}", what, bdpt, path);
+    };
+
+    // The quad Cornell box with its light swapped, lower so that the gap to the ceiling doesn't
+    // make fireflies in both
+    let (quad_box, _, material_vec) = obj_quad_cornell_box();
+    let with_light = |light: Arc<dyn Hittable>| {
+        let mut world = hittable_list::new();
+        for (i, obj) in quad_box.obj_list.iter().enumerate() {
+            world.obj_list.push(if i == 5 { Arc::clone(&light) } else { Arc::clone(obj) });
+        }
+        world.construct_bvh(0., 1.);
+        let mut lights = hittable_list::new();
+        lights.obj_list.push(light);
+        (world, lights)
+    };
+    let glow = Arc::clone(&material_vec[3]);
+
+    let (world, lights) = with_light(Arc::new(quad::from(point3::from(343., 450., 332.), vec3::from(-130., 0., 0.), vec3::from(0., 0., -105.), Arc::clone(&glow))));
+    close(film_means(&world, &lights, &lights, n), "area light");
+    // The same light left out of bdpt's lights, only s = 0 finds it
+    close(film_means(&world, &hittable_list::new(), &lights, n), "unlisted emitter");
+
+    let (world, lights) = with_light(Arc::new(xz_rect::from(213., 343., 227., 332., 450., Arc::clone(&glow))));
+    close(film_means(&world, &lights, &lights, n), "rect light");
+
+    let (world, lights, _) = obj_delta_lights_cornell_box();
+    close(film_means(&world, &lights, &lights, n), "point and spot");
+}
@@ -22,6 +22,7 @@ use crate::rtow_math::{
 use crate::materials::*;
 use std::sync::*;
 
+#[allow(clippy::approx_constant)]
 fn light_hits(r: &ray, lights: Arc<Vec<light>>, obj: Arc<hittable_list>) -> colorRGB {
     // Direct ray to find first object from camera
     // Then from point hit to all lights for each contribution
@@ -41,7 +42,7 @@ fn light_hits(r: &ray, lights: Arc<Vec<light>>, obj: Arc<hittable_list>) -> colo
     color
 }
 
-fn ray_hits(r: &ray, obj: Arc<hittable_list>, depth_: i32, debug_iter_vec: Arc<Mutex<Vec<i32>>>, bg_col: colorRGB) ->  colorRGB {
+fn ray_hits(r: &ray, obj: Arc<hittable_list>, depth_: i32, debug_iter_vec: Arc<Mutex<Vec<i32>>>, bg: &dyn Background) ->  colorRGB {
     if(depth_ < 1) {return colorRGB::new()}
 
     let next_depth = depth_ -1;
@@ -51,14 +52,11 @@ fn ray_hits(r: &ray, obj: Arc<hittable_list>, depth_: i32, debug_iter_vec: Arc<M
 
     if !obj.hit_bvh(0.0001, std::f64::INFINITY, &mut rec, r) {
         debug_iter_vec.lock().unwrap().push(rec.iters);
-        return bg_col;
-    }
-    if rec.uv.v[0].is_nan() || rec.uv.v[1].is_nan() {
-        let mut test = false;
-        test = true;
+        return bg.value(&r.dir);
     }
+    rec.set_footprint(r);
     let mut scattered = ray::new();
-    let emitted = rec.mat.emitted(rec.uv.v[0], rec.uv.v[1], &rec.p);
+    let emitted = rec.mat.emitted_hit(r, &rec);
 
     //let mut attenuation = colorRGB::new();
     //let res1 = rec.mat.scatter_tex(r, &rec, &mut attenuation, &mut scattered);
@@ -81,8 +79,9 @@ fn ray_hits(r: &ray, obj: Arc<hittable_list>, depth_: i32, debug_iter_vec: Arc<M
     //    }
     //}
 
+    scattered.differential = rec.bounce_differential(r, &scattered, rec.mat.scattering_pdf(r, &rec, &scattered));
     debug_iter_vec.lock().unwrap().push(rec.iters);
-    emitted + ray_hits(&scattered, obj, next_depth, Arc::clone(&debug_iter_vec), bg_col) * attenuation
+    emitted + ray_hits(&scattered, obj, next_depth, Arc::clone(&debug_iter_vec), bg) * attenuation
 }
 
 enum Pixel {
@@ -98,6 +97,8 @@ pub fn render() {
 
     let (cam, image_width, image_height) = cam_final_scene();
     let (iw_f64, ih_f64) = (image_width as f64, image_height as f64);
+    // Ray differentials one pixel apart, narrower with more samples as each one covers less of the pixel
+    let diff_scale = (1. / (samples as f64).sqrt()).max(0.125);
 
     // SETUP Objects and materials 
     
@@ -119,7 +120,7 @@ pub fn render() {
     //let mut sender: mpsc::Sender<Pixel>;
     //let mut receiver: mpsc::Receiver<Pixel>;
     //let (sender, receiver) = mpsc::channel();
-    let bg_col = colorRGB::new();
+    let bg: Arc<dyn Background> = Arc::new(colorRGB::new());
     let arc_hit = Arc::new(hittables);
     let mut arc_iters: Arc<Mutex<Vec<i32>>> = Arc::new(Mutex::new(Vec::new()));
 
@@ -148,6 +149,7 @@ pub fn render() {
                     let hit_arc = Arc::clone(&arc_hit);
                     let curr_pixel = Arc::clone(v);
                     let clone_iters = Arc::clone(&arc_iters);
+                    let task_bg = Arc::clone(&bg);
 
                     tp.add_task(move || {
                         let mut pixel = colorRGB::new();
@@ -155,8 +157,8 @@ pub fn render() {
                             //let x_sum = (s as f64).sqrt()
                             let u = (j as f64 + rand_f64()) / (iw_f64 - 1.);
                             let v = (i as f64 + rand_f64()) / (ih_f64 - 1.);
-                            let r = cam.focus_time_ray(u, v);
-                            let ambient_indirect = ray_hits(&r, Arc::clone(&hit_arc), depth, Arc::clone(&clone_iters), bg_col);
+                            let r = cam.focus_time_ray_diff(u, v, diff_scale / (iw_f64 - 1.), diff_scale / (ih_f64 - 1.));
+                            let ambient_indirect = ray_hits(&r, Arc::clone(&hit_arc), depth, Arc::clone(&clone_iters), task_bg.as_ref());
                             
                             pixel = pixel + ambient_indirect;// + lights_direct;
                         }                   
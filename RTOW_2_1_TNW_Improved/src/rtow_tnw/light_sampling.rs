@@ -0,0 +1,189 @@
+use simple_stopwatch::Stopwatch;
+
+use crate::objects::prelude::*;
+use crate::rtow_math::prelude::*;
+use crate::materials::prelude::*;
+use std::sync::*;
+
+use rayon::prelude::*;
+
+// Path tracer with next event estimation
+// At each non specular bounce a point on a light is sampled and a shadow ray is thrown to it.
+// Lights can also be found by the scattered ray, so both estimates are weighted with
+// multiple importance sampling (power heuristic) to not count the light twice.
+// Materials opt in by implementing eval() and scattering_pdf(), lights by implementing
+// pdf_value() and random() in Hittable.
+// What misses everything sees comes from a Background, an environment_light in the lights list
+// is also sampled directly like any other light.
+// Delta lights (points, spots, directional) in the lights list can't be found by the bsdf, their
+// samples take no mis weight.
+// Which light gets sampled is up to a LightSampler: a plain hittable_list picks uniformly,
+// power_light_sampler and light_bvh are for scenes with many lights.
+
+pub fn power_heuristic(pdf_a: f64, pdf_b: f64) -> f64 {
+    let (a2, b2) = (pdf_a * pdf_a, pdf_b * pdf_b);
+    if a2 + b2 <= 0. { 0. } else { a2 / (a2 + b2) }
+}
+
+/// Random point of lights seen from rec: (brdf * cos, emitted light, mis weight / light pdf)
+/// None when the light is behind, blocked or out of the brdf
+pub fn light_sample(r: &ray, rec: &hit_record, world: &hittable_list, lights: &dyn LightSampler, bg: &dyn Background) -> Option<(colorRGB, colorRGB, f64)> {
+    let (idx, pmf) = lights.sample_light(&rec.p, rand_f64())?;
+    let light = &lights.lights()[idx];
+    if light.is_delta() { return delta_light_sample(r, rec, world, light.as_ref(), pmf) }
+
+    let shadow_r = ray::from_t(rec.p, light.random(&rec.p), r.time);
+    // Any light could have sent this direction, not only the one picked
+    let light_pdf = lights.light_pdf(&rec.p, &shadow_r.dir);
+    if light_pdf <= 0. { return None }
+
+    let f = rec.mat.eval(r, rec, &shadow_r);
+    if f.near_zero() { return None }
+
+    // Whatever the shadow ray finds first is what gets lit, if it is not emissive the light is blocked
+    // Nothing at all means it reaches the background
+    let mut light_rec = hit_record::new();
+    let emitted = if world.hit_bvh(0.0001, INFINITY, &mut light_rec, &shadow_r) {
+        light_rec.mat.emitted_hit(&shadow_r, &light_rec)
+    } else { bg.value(&shadow_r.dir) };
+    if emitted.near_zero() { return None }
+
+    let weight = power_heuristic(light_pdf, rec.mat.scattering_pdf(r, rec, &shadow_r));
+    Some((f, emitted, weight / light_pdf))
+}
+
+/// light_sample() for a delta light picked with probability pmf, anything in between blocks it
+fn delta_light_sample(r: &ray, rec: &hit_record, world: &hittable_list, light: &dyn Hittable, pmf: f64) -> Option<(colorRGB, colorRGB, f64)> {
+    let (dir, dist, incoming) = light.sample_delta(&rec.p)?;
+    let shadow_r = ray::from_t(rec.p, dir.unit_vec(), r.time);
+    let f = rec.mat.eval(r, rec, &shadow_r);
+    if f.near_zero() { return None }
+
+    let mut blocker = hit_record::new();
+    if world.hit_bvh(0.0001, dist - 0.0001, &mut blocker, &shadow_r) { return None }
+    Some((f, incoming, 1. / pmf))
+}
+
+/// Direct light from a random point of lights, already weighted against bsdf sampling
+fn sample_lights(r: &ray, rec: &hit_record, world: &hittable_list, lights: &dyn LightSampler, bg: &dyn Background) -> colorRGB {
+    match light_sample(r, rec, world, lights, bg) {
+        Some((f, emitted, weight)) => f * emitted * weight,
+        None => colorRGB::new(),
+    }
+}
+
+pub fn path_color(cam_r: &ray, world: &hittable_list, lights: &dyn LightSampler, bg: &dyn Background, max_depth: i32) -> colorRGB {
+    let mut col = colorRGB::new();
+    let mut throughput = colorRGB::one();
+    let mut r = ray::from_t(cam_r.origin, cam_r.dir, cam_r.time);
+    r.differential = cam_r.differential;
+
+    // Pdf of the bsdf sample that generated r, 0 when it comes from the camera or a specular bounce
+    let mut bsdf_pdf = 0.;
+    let mut prev_p = r.origin;
+
+    for _ in 0..max_depth {
+        let mut rec = hit_record::new();
+        if !world.hit_bvh(0.0001, INFINITY, &mut rec, &r) {
+            let weight = if bsdf_pdf > 0. { power_heuristic(bsdf_pdf, lights.light_pdf(&prev_p, &r.dir)) } else { 1. };
+            col = col + throughput * bg.value(&r.dir) * weight;
+            break;
+        }
+        rec.set_footprint(&r);
+
+        let emitted = rec.mat.emitted_hit(&r, &rec);
+        if !emitted.near_zero() {
+            let weight = if bsdf_pdf > 0. { power_heuristic(bsdf_pdf, lights.light_pdf(&prev_p, &r.dir)) } else { 1. };
+            col = col + throughput * emitted * weight;
+        }
+
+        col = col + throughput * sample_lights(&r, &rec, world, lights, bg);
+
+        let mut attenuation = colorRGB::new();
+        let mut scattered = ray::new();
+        if !rec.mat.scatter_tex(&r, &rec, &mut attenuation, &mut scattered) { break }
+
+        bsdf_pdf = rec.mat.scattering_pdf(&r, &rec, &scattered);
+        scattered.differential = rec.bounce_differential(&r, &scattered, bsdf_pdf);
+        throughput = throughput * attenuation;
+        prev_p = rec.p;
+        r = scattered;
+
+        if throughput.near_zero() { break }
+    }
+
+    col
+}
+
+use crate::rtow_tnw::*;
+
+pub fn render() {
+    let (cam, image_width, image_height) = cam_cornell_box();
+    let (world, lights, material_vec) = obj_quad_cornell_box();
+    render_scene(&cam, image_width, image_height, &world, &lights, &colorRGB::new());
+}
+
+/// Point and spot lights, only reached by next event estimation
+pub fn render_delta_lights() {
+    let (cam, image_width, image_height) = cam_cornell_box();
+    let (world, lights, material_vec) = obj_delta_lights_cornell_box();
+    render_scene(&cam, image_width, image_height, &world, &lights, &colorRGB::new());
+}
+
+/// Hundreds of glowing spheres, each light picked by how much it brings to the shading point
+pub fn render_emissive_spheres() {
+    let (cam, image_width, image_height) = cam_emissive_spheres();
+    let (world, lights, material_vec) = obj_emissive_spheres();
+    let light_tree = light_bvh::from(&lights);
+    render_scene(&cam, image_width, image_height, &world, &light_tree, &colorRGB::from(0.01, 0.01, 0.02));
+}
+
+/// Image based lighting: an .hdr sky sampled by luminance, mis weighted against the bsdf
+pub fn render_environment() {
+    let (cam, image_width, image_height) = cam_emissive_spheres();
+    let (world, lights, environment, material_vec) = obj_environment_spheres();
+    render_scene(&cam, image_width, image_height, &world, &lights, environment.as_ref());
+}
+
+/// Outdoor daylight without any image: Preetham sky and its sun
+pub fn render_daylight() {
+    let (cam, image_width, image_height) = cam_emissive_spheres();
+    let (world, lights, sky, material_vec) = obj_daylight_spheres();
+    render_scene(&cam, image_width, image_height, &world, &lights, sky.as_ref());
+}
+
+fn render_scene(cam: &camera, image_width: i32, image_height: i32, world: &hittable_list, lights: &dyn LightSampler, bg: &dyn Background) {
+    let mut timer = Stopwatch::start_new();
+    let (iw_f64, ih_f64) = (image_width as f64, image_height as f64);
+
+    // Ray differentials one pixel apart, narrower with more samples as each one covers less of the pixel
+    let diff_scale = (1. / (samples as f64).sqrt()).max(0.125);
+
+    println!("P3\n{} {}\n255\n", image_width, image_height);
+
+    let mut coords: Vec<(i32, i32)> = Vec::new();
+    for i in (0..image_height).rev() {
+        for j in 0..image_width {
+            coords.push((i, j));
+        }
+    }
+
+    let image: Vec<colorRGB> = coords.into_par_iter().map(|(i, j)| {
+        let mut pixel = colorRGB::new();
+        for s in 0..samples {
+            let u = (j as f64 + rand_f64()) / (iw_f64 - 1.);
+            let v = (i as f64 + rand_f64()) / (ih_f64 - 1.);
+            let r = cam.focus_time_ray_diff(u, v, diff_scale / (iw_f64 - 1.), diff_scale / (ih_f64 - 1.));
+            pixel = pixel + path_color(&r, world, lights, bg, depth);
+        }
+        pixel
+    }).collect();
+
+    eprintln!("Tasks finished running at {} ms", timer.ms());
+
+    for col in image.iter() {
+        col.write_color(samples as f64);
+    }
+
+    eprintln!("Took {} ms", timer.ms());
+}
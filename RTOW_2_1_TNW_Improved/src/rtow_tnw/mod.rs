@@ -1,6 +1,9 @@
 pub mod rayon_test;
 pub mod rayon_chunks;
 pub mod rayon_tiles;
+pub mod light_sampling;
+pub mod spectral;
+pub mod bdpt;
 
 pub mod final_scene_render;
 use std::sync::mpsc;
@@ -65,7 +68,7 @@ pub fn obj_final_scene() -> (hittable_list, Vec<Arc<dyn Material>>) {
     hittables.obj_list.push(Arc::new(ground_boxes));
 
     // Emitters
-    material_vec.push(Arc::new(Diffuse_Emissive{albedo: colorRGB::one() * 7., tex: Arc::new(Solid_Color::from_colorRGB(colorRGB::one()))}));
+    material_vec.push(Arc::new(Diffuse_Emissive{albedo: colorRGB::one() * 7., tex: Arc::new(Solid_Color::from_colorRGB(colorRGB::one())), two_sided: true}));
     hittables.obj_list.push(Arc::new(xz_rect::from(123., 423., 147., 412., 553., Arc::clone(&material_vec[1]))));
 
     // Base Spheres
@@ -125,4 +128,239 @@ pub fn obj_final_scene() -> (hittable_list, Vec<Arc<dyn Material>>) {
     hittables.construct_bvh(0., 1.);
 
     (hittables, material_vec)
-}
\ No newline at end of file
+}
+
+pub fn cam_cornell_box() -> (camera, i32, i32) {
+    let aspect_ratio = 1.;
+    let image_width = 400;
+    let image_height = (image_width as f64 / aspect_ratio) as i32;
+
+    let og = point3::from(278.,278.,-800.);
+    let lookat = point3::from(278.,278.,0.);
+    let vup = vec3::from(0., 1.,0.);
+    let focus_dist = (og - lookat).length();
+    let aperture = 0.;
+    (
+        camera::from_all(og, lookat, vup, 40., aspect_ratio, aperture, focus_dist, 0., 1.),
+        image_width,
+        image_height,
+    )
+}
+
+/// Cornell box made of quads, returns the lights apart to be sampled by light_sampling
+pub fn obj_quad_cornell_box() -> (hittable_list, hittable_list, Vec<Arc<dyn Material>>) {
+    let mut hittables: hittable_list = hittable_list::new();
+    let mut lights: hittable_list = hittable_list::new();
+
+    let mut material_vec : Vec<Arc<dyn Material>> = Vec::new();
+
+    material_vec.push(Arc::new(lambertian::new(colorRGB::one(), Arc::new(Solid_Color::from(0.65, 0.05, 0.05)))));
+    material_vec.push(Arc::new(lambertian::new(colorRGB::one(), Arc::new(Solid_Color::from(0.73, 0.73, 0.73)))));
+    material_vec.push(Arc::new(lambertian::new(colorRGB::one(), Arc::new(Solid_Color::from(0.12, 0.45, 0.15)))));
+    material_vec.push(Arc::new(Diffuse_Emissive{albedo: colorRGB::one(), tex: Arc::new(Solid_Color::from(15., 15., 15.)), two_sided: true}));
+
+    hittables.obj_list.push(Arc::new(quad::from(point3::from(555., 0., 0.), vec3::from(0., 555., 0.), vec3::from(0., 0., 555.), Arc::clone(&material_vec[2]))));
+    hittables.obj_list.push(Arc::new(quad::from(point3::from(0., 0., 0.), vec3::from(0., 555., 0.), vec3::from(0., 0., 555.), Arc::clone(&material_vec[0]))));
+    hittables.obj_list.push(Arc::new(quad::from(point3::from(0., 0., 0.), vec3::from(555., 0., 0.), vec3::from(0., 0., 555.), Arc::clone(&material_vec[1]))));
+    hittables.obj_list.push(Arc::new(quad::from(point3::from(555., 555., 555.), vec3::from(-555., 0., 0.), vec3::from(0., 0., -555.), Arc::clone(&material_vec[1]))));
+    hittables.obj_list.push(Arc::new(quad::from(point3::from(0., 0., 555.), vec3::from(555., 0., 0.), vec3::from(0., 555., 0.), Arc::clone(&material_vec[1]))));
+
+    // Light facing down, the same object goes in both lists
+    let light: Arc<dyn Hittable> = Arc::new(quad::from(point3::from(343., 554., 332.), vec3::from(-130., 0., 0.), vec3::from(0., 0., -105.), Arc::clone(&material_vec[3])));
+    hittables.obj_list.push(Arc::clone(&light));
+    lights.obj_list.push(light);
+
+    hittables.obj_list.push(Arc::new(
+        translated::new( Box::new(
+            rotated::new(
+                Box::new(quad_box(point3::new(), point3::from(165., 330., 165.), Arc::clone(&material_vec[1]))),
+                vec3::from(0., 15., 0.))),
+        vec3::from(265., 0., 295.))));
+
+    hittables.obj_list.push(Arc::new(
+        translated::new( Box::new(
+            rotated::new(
+                Box::new(quad_box(point3::new(), point3::from(165., 165., 165.), Arc::clone(&material_vec[1]))),
+                vec3::from(0., -18., 0.))),
+        vec3::from(130., 0., 65.))));
+
+    hittables.construct_bvh(0., 1.);
+
+    (hittables, lights, material_vec)
+}
+
+/// Quad Cornell box with dispersive spheres instead of boxes, for the spectral integrator
+pub fn obj_spectral_cornell_box() -> (hittable_list, hittable_list, Vec<Arc<dyn Material>>) {
+    let (quad_box, lights, mut material_vec) = obj_quad_cornell_box();
+
+    // Walls and light are the first 6 objects, the 2 boxes are left out
+    let mut hittables: hittable_list = hittable_list::new();
+    for obj in quad_box.obj_list.iter().take(6) {
+        hittables.obj_list.push(Arc::clone(obj));
+    }
+
+    material_vec.push(Arc::new(dielectric::diamond()));
+    material_vec.push(Arc::new(dielectric::from_dispersion(Dispersion::Cauchy(1.6, 0.05))));
+
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(190., 90., 190.), 90., Arc::clone(&material_vec[4]))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(380., 120., 330.), 120., Arc::clone(&material_vec[5]))));
+
+    hittables.construct_bvh(0., 1.);
+
+    (hittables, lights, material_vec)
+}
+
+/// Quad Cornell box with a glass and a white sphere instead of boxes: caustics under the glass
+/// one that only bdpt resolves in reasonable time
+pub fn obj_glass_cornell_box() -> (hittable_list, hittable_list, Vec<Arc<dyn Material>>) {
+    let (quad_box, lights, mut material_vec) = obj_quad_cornell_box();
+
+    // Walls and light are the first 6 objects, the 2 boxes are left out
+    let mut hittables: hittable_list = hittable_list::new();
+    for obj in quad_box.obj_list.iter().take(6) {
+        hittables.obj_list.push(Arc::clone(obj));
+    }
+
+    material_vec.push(Arc::new(dielectric::from(0., 1.5, Arc::new(Solid_Color::from(1., 1., 1.)))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(370., 100., 230.), 100., Arc::clone(&material_vec[material_vec.len() - 1]))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(170., 90., 350.), 90., Arc::clone(&material_vec[1]))));
+
+    hittables.construct_bvh(0., 1.);
+
+    (hittables, lights, material_vec)
+}
+
+/// Quad Cornell box without its area light, lit by a point light under the ceiling and a bluish
+/// spotlight on the tall box: hard shadows and the soft edge of the spot cone
+pub fn obj_delta_lights_cornell_box() -> (hittable_list, hittable_list, Vec<Arc<dyn Material>>) {
+    let (quad_box, _, material_vec) = obj_quad_cornell_box();
+
+    // The area light is the 6th object
+    let mut hittables: hittable_list = hittable_list::new();
+    for (i, obj) in quad_box.obj_list.iter().enumerate() {
+        if i != 5 { hittables.obj_list.push(Arc::clone(obj)) }
+    }
+
+    let mut lights: hittable_list = hittable_list::new();
+    lights.obj_list.push(Arc::new(light::new_point(point3::from(278., 500., 278.), 40000., colorRGB::from(1., 0.9, 0.8))));
+    lights.obj_list.push(Arc::new(light::new_spotlight(point3::from(450., 540., 150.), vec3::from(-0.3, -1., 0.6), 150000., colorRGB::from(0.4, 0.6, 1.))));
+
+    hittables.construct_bvh(0., 1.);
+
+    (hittables, lights, material_vec)
+}
+
+pub fn cam_emissive_spheres() -> (camera, i32, i32) {
+    let aspect_ratio = 3. / 2.;
+    let image_width = 600;
+    let image_height = (image_width as f64 / aspect_ratio) as i32;
+
+    let og = point3::from(13., 2., 3.);
+    let lookat = point3::new();
+    let vup = vec3::from(0., 1., 0.);
+    let focus_dist = 10.;
+    let aperture = 0.;
+    (
+        camera::from_all(og, lookat, vup, 20., aspect_ratio, aperture, focus_dist, 0., 1.),
+        image_width,
+        image_height,
+    )
+}
+
+/// Random spheres of the first book at night, a third of the small ones glow: hundreds of lights
+/// for light_bvh and power_light_sampler. Returns the glowing ones apart as lights
+pub fn obj_emissive_spheres() -> (hittable_list, hittable_list, Vec<Arc<dyn Material>>) {
+    let mut hittables: hittable_list = hittable_list::new();
+    let mut lights: hittable_list = hittable_list::new();
+
+    let mut material_vec : Vec<Arc<dyn Material>> = Vec::new();
+
+    material_vec.push(Arc::new(lambertian::new(colorRGB::one(), Arc::new(Solid_Color::from(0.5, 0.5, 0.5)))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(0., -1000., 0.), 1000., Arc::clone(&material_vec[0]))));
+
+    for i in -11..11 {
+        for j in -11..11 {
+            let mat_rng = rand_f64();
+            let center = point3::from(i as f64 + 0.9 * rand_f64(), 0.2, j as f64 + 0.9 * rand_f64());
+            if (center - point3::from(4., 0.2, 0.)).length() <= 0.9 { continue }
+
+            let col = colorRGB::from(rand_f64_r(0.5, 1.), rand_f64_r(0.5, 1.), rand_f64_r(0.5, 1.));
+            let mat: Arc<dyn Material> = if mat_rng < 0.35 {
+                // Some much brighter than others, where power based picking pays off
+                Arc::new(Diffuse_Emissive { albedo: col * rand_f64_r(1., 10.), ..Diffuse_Emissive::new(Arc::new(Solid_Color::from(1., 1., 1.))) })
+            } else if mat_rng < 0.8 {
+                Arc::new(lambertian::new(colorRGB::one(), Arc::new(Solid_Color::from_colorRGB(col))))
+            } else if mat_rng < 0.95 {
+                Arc::new(metal::new(rand_f64_r(0., 0.5), Arc::new(Solid_Color::from_colorRGB(col))))
+            } else {
+                Arc::new(dielectric::from(0., 1.5, Arc::new(Solid_Color::from(1., 1., 1.))))
+            };
+            material_vec.push(Arc::clone(&mat));
+
+            let obj: Arc<dyn Hittable> = Arc::new(sphere::from_mat(center, 0.2, mat));
+            if mat_rng < 0.35 { lights.obj_list.push(Arc::clone(&obj)) }
+            hittables.obj_list.push(obj);
+        }
+    }
+
+    material_vec.push(Arc::new(dielectric::from(0., 1.5, Arc::new(Solid_Color::from(1., 1., 1.)))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(0., 1., 0.), 1., Arc::clone(&material_vec[material_vec.len() - 1]))));
+
+    material_vec.push(Arc::new(metal::new(0., Arc::new(Solid_Color::from(0.7, 0.6, 0.5)))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(4., 1., 0.), 1., Arc::clone(&material_vec[material_vec.len() - 1]))));
+
+    material_vec.push(Arc::new(lambertian::new(colorRGB::one(), Arc::new(Solid_Color::from(0.7, 0.6, 0.5)))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(-4., 1., 0.), 1., Arc::clone(&material_vec[material_vec.len() - 1]))));
+
+    hittables.construct_bvh(0., 1.);
+
+    (hittables, lights, material_vec)
+}
+
+/// Ground and the three big spheres of the first book, no lights: for scenes lit from the sky
+pub fn obj_big_spheres() -> (hittable_list, Vec<Arc<dyn Material>>) {
+    let mut hittables: hittable_list = hittable_list::new();
+
+    let mut material_vec : Vec<Arc<dyn Material>> = Vec::new();
+
+    material_vec.push(Arc::new(lambertian::new(colorRGB::one(), Arc::new(Solid_Color::from(0.5, 0.5, 0.5)))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(0., -1000., 0.), 1000., Arc::clone(&material_vec[0]))));
+
+    material_vec.push(Arc::new(dielectric::from(0., 1.5, Arc::new(Solid_Color::from(1., 1., 1.)))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(0., 1., 0.), 1., Arc::clone(&material_vec[1]))));
+
+    material_vec.push(Arc::new(metal::new(0., Arc::new(Solid_Color::from(0.7, 0.6, 0.5)))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(4., 1., 0.), 1., Arc::clone(&material_vec[2]))));
+
+    material_vec.push(Arc::new(lambertian::new(colorRGB::one(), Arc::new(Solid_Color::from(0.7, 0.6, 0.5)))));
+    hittables.obj_list.push(Arc::new(sphere::from_mat(point3::from(-4., 1., 0.), 1., Arc::clone(&material_vec[3]))));
+
+    hittables.construct_bvh(0., 1.);
+
+    (hittables, material_vec)
+}
+
+/// The big spheres lit only by sky_sun.hdr. The environment goes both in the lights, to be sampled
+/// towards its sun, and as the Background the misses see
+pub fn obj_environment_spheres() -> (hittable_list, hittable_list, Arc<environment_light>, Vec<Arc<dyn Material>>) {
+    let (hittables, material_vec) = obj_big_spheres();
+    let mut lights: hittable_list = hittable_list::new();
+
+    let environment = Arc::new(environment_light::load(&String::from("sky_sun.hdr"), 1., 0.));
+    lights.obj_list.push(Arc::clone(&environment) as Arc<dyn Hittable>);
+
+    (hittables, lights, environment, material_vec)
+}
+
+/// The big spheres under a Preetham sky with an afternoon sun. The sky is the Background, the sun
+/// disk and a tabulated copy of the sky go in the lights to be sampled directly
+pub fn obj_daylight_spheres() -> (hittable_list, hittable_list, Arc<physical_sky>, Vec<Arc<dyn Material>>) {
+    let (hittables, material_vec) = obj_big_spheres();
+    let mut lights: hittable_list = hittable_list::new();
+
+    let sky = Arc::new(physical_sky::from_angles(35., 60., 3., colorRGB::from(0.3, 0.3, 0.3)));
+    lights.obj_list.push(Arc::new(sky.sun_light()));
+    lights.obj_list.push(Arc::new(sky.sky_light(256)));
+
+    (hittables, lights, sky, material_vec)
+}
@@ -54,7 +54,7 @@ fn ray_hits(r: &ray, obj: &Arc<hittable_list>, depth_: i32, bg_col: colorRGB, la
     }
 
     let mut scattered = ray::new();
-    let emitted = rec.mat.emitted(rec.uv.v[0], rec.uv.v[1], &rec.p);
+    let emitted = rec.mat.emitted_hit(r, &rec);
 
     if !rec.mat.scatter_tex(r, &rec, &mut attenuation, &mut scattered) {
         return (scattered, emitted, colorRGB::new(), true);
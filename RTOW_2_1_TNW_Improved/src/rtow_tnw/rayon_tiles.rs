@@ -59,9 +59,10 @@ fn ray_hits(r: &ray, obj: &Arc<hittable_list>, depth_: i32, bg_col: colorRGB, la
             return (ray::new(), colorRGB::new(), bg_col, true);
         }
     }
+    rec.set_footprint(r);
 
     let mut scattered = ray::new();
-    let emitted = rec.mat.emitted(rec.uv.v[0], rec.uv.v[1], &rec.p);
+    let emitted = rec.mat.emitted_hit(r, &rec);
 
     {
         let span_scatter = span!(Level::TRACE, "Scatter");
@@ -70,6 +71,7 @@ fn ray_hits(r: &ray, obj: &Arc<hittable_list>, depth_: i32, bg_col: colorRGB, la
             return (scattered, emitted, colorRGB::new(), true);
         }
     }
+    scattered.differential = rec.bounce_differential(r, &scattered, rec.mat.scattering_pdf(r, &rec, &scattered));
     
     (
         scattered,
@@ -94,6 +96,8 @@ pub fn render() {
 
     let (cam, image_width, image_height) = cam_final_scene();
     let (iw_f64, ih_f64) = (image_width as f64, image_height as f64);
+    // Ray differentials one pixel apart, narrower with more samples as each one covers less of the pixel
+    let diff_scale = (1. / (samples as f64).sqrt()).max(0.125);
     let bg_col = colorRGB::new();
 
     // SETUP Objects and materials 
@@ -200,7 +204,7 @@ pub fn render() {
 
                     let u = (pixel.j as f64 + rand_f64()) / (iw_f64 - 1.);
                     let v = (pixel.i as f64 + rand_f64()) / (ih_f64 - 1.);
-                    let mut r = cam.focus_time_ray(u, v);
+                    let mut r = cam.focus_time_ray_diff(u, v, diff_scale / (iw_f64 - 1.), diff_scale / (ih_f64 - 1.));
                     
                     ambient_indirect = colorRGB::new();
                     attenuation_bounces = colorRGB::one();
@@ -0,0 +1,128 @@
+use simple_stopwatch::Stopwatch;
+
+use crate::objects::prelude::*;
+use crate::rtow_math::prelude::*;
+use crate::materials::prelude::*;
+use crate::rtow_tnw::light_sampling::*;
+use std::sync::*;
+
+use rayon::prelude::*;
+
+// Spectral version of light_sampling, each path carries 4 wavelengths (hero wavelength sampling)
+// Materials and textures stay rgb, their values are turned into spectra at each wavelength.
+// The ray keeps the hero wavelength so dispersive materials can bend it, when that happens
+// the other 3 wavelengths would need a different path and are dropped.
+
+/// Radiance along cam_r at each of the wavelengths
+pub fn path_spectrum(cam_r: &ray, wavelengths: &[f64; 4], world: &hittable_list, lights: &dyn LightSampler, bg: &dyn Background, max_depth: i32) -> [f64; 4] {
+    let mut radiance = [0.; 4];
+    let mut throughput = [1.; 4];
+    let mut r = ray::from_t(cam_r.origin, cam_r.dir, cam_r.time);
+    r.wavelength = wavelengths[0];
+    r.differential = cam_r.differential;
+
+    let mut bsdf_pdf = 0.;
+    let mut prev_p = r.origin;
+    let mut hero_only = false;
+
+    for _ in 0..max_depth {
+        let mut rec = hit_record::new();
+        if !world.hit_bvh(0.0001, INFINITY, &mut rec, &r) {
+            let bg_col = bg.value(&r.dir);
+            let weight = if bsdf_pdf > 0. { power_heuristic(bsdf_pdf, lights.light_pdf(&prev_p, &r.dir)) } else { 1. };
+            for i in 0..4 { radiance[i] += throughput[i] * rgb_to_spectrum(&bg_col, wavelengths[i]) * weight }
+            break;
+        }
+        rec.set_footprint(&r);
+
+        let emitted = rec.mat.emitted_hit(&r, &rec);
+        if !emitted.near_zero() {
+            let weight = if bsdf_pdf > 0. { power_heuristic(bsdf_pdf, lights.light_pdf(&prev_p, &r.dir)) } else { 1. };
+            for i in 0..4 { radiance[i] += throughput[i] * rgb_to_spectrum(&emitted, wavelengths[i]) * weight }
+        }
+
+        if let Some((f, light, weight)) = light_sample(&r, &rec, world, lights, bg) {
+            for i in 0..4 { radiance[i] += throughput[i] * rgb_to_spectrum(&f, wavelengths[i]) * rgb_to_spectrum(&light, wavelengths[i]) * weight }
+        }
+
+        let mut attenuation = colorRGB::new();
+        let mut scattered = ray::new();
+        if !rec.mat.scatter_tex(&r, &rec, &mut attenuation, &mut scattered) { break }
+
+        // From here the path only exists for the hero, which now stands for all 4
+        if rec.mat.is_dispersive() && !hero_only {
+            hero_only = true;
+            throughput[0] *= 4.;
+            for t in throughput.iter_mut().skip(1) { *t = 0. }
+        }
+
+        for i in 0..4 { throughput[i] *= rgb_to_spectrum(&attenuation, wavelengths[i]) }
+        bsdf_pdf = rec.mat.scattering_pdf(&r, &rec, &scattered);
+        scattered.differential = rec.bounce_differential(&r, &scattered, bsdf_pdf);
+        prev_p = rec.p;
+        r = scattered;
+        r.wavelength = wavelengths[0];
+
+        if throughput.iter().all(|t| *t <= 0.) { break }
+    }
+
+    radiance
+}
+
+/// Sample of the pixel colour in XYZ, one path with 4 wavelengths
+pub fn sample_xyz(cam_r: &ray, world: &hittable_list, lights: &dyn LightSampler, bg: &dyn Background, max_depth: i32) -> vec3 {
+    let wavelengths = sample_wavelengths();
+    let radiance = path_spectrum(cam_r, &wavelengths, world, lights, bg, max_depth);
+
+    // Each wavelength has pdf 1 / range and they share the sample
+    let weight = (LAMBDA_MAX - LAMBDA_MIN) / 4.;
+    let mut xyz = vec3::new();
+    for i in 0..4 {
+        xyz = xyz + cie_xyz(wavelengths[i]) * (radiance[i] * weight);
+    }
+    xyz
+}
+
+use crate::rtow_tnw::*;
+
+pub fn render() {
+    let mut timer = Stopwatch::start_new();
+
+    let (cam, image_width, image_height) = cam_cornell_box();
+    let (iw_f64, ih_f64) = (image_width as f64, image_height as f64);
+    let bg_col = colorRGB::new();
+    let white = spectral_white();
+
+    let (world, lights, material_vec) = obj_spectral_cornell_box();
+
+    let diff_scale = (1. / (samples as f64).sqrt()).max(0.125);
+
+    println!("P3\n{} {}\n255\n", image_width, image_height);
+
+    let mut coords: Vec<(i32, i32)> = Vec::new();
+    for i in (0..image_height).rev() {
+        for j in 0..image_width {
+            coords.push((i, j));
+        }
+    }
+
+    let image: Vec<colorRGB> = coords.into_par_iter().map(|(i, j)| {
+        let mut xyz = vec3::new();
+        for s in 0..samples {
+            let u = (j as f64 + rand_f64()) / (iw_f64 - 1.);
+            let v = (i as f64 + rand_f64()) / (ih_f64 - 1.);
+            let r = cam.focus_time_ray_diff(u, v, diff_scale / (iw_f64 - 1.), diff_scale / (ih_f64 - 1.));
+            xyz = xyz + sample_xyz(&r, &world, &lights, &bg_col, depth);
+        }
+        let rgb = xyz_to_srgb(&xyz);
+        colorRGB::from(rgb.v[0] / white.v[0], rgb.v[1] / white.v[1], rgb.v[2] / white.v[2])
+    }).collect();
+
+    eprintln!("Tasks finished running at {} ms", timer.ms());
+
+    for col in image.iter() {
+        col.write_color(samples as f64);
+    }
+
+    eprintln!("Took {} ms", timer.ms());
+}